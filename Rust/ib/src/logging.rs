@@ -0,0 +1,29 @@
+use crate::config::{LogFormat, LoggingConfig};
+use std::io::IsTerminal;
+use tower_http::classify::{ServerErrorsAsFailures, SharedClassifier};
+use tower_http::trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer};
+use tower_http::LatencyUnit;
+use tracing::Level;
+use tracing_subscriber::EnvFilter;
+
+/// Installs the global subscriber; `Config::load` has already checked the
+/// filter directives.
+pub fn init(config: &LoggingConfig) {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(&config.level))
+        .with_ansi(std::io::stdout().is_terminal());
+    match config.format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Pretty => builder.pretty().init(),
+        LogFormat::Json => builder.json().flatten_event(true).with_span_list(false).init(),
+    }
+}
+
+/// One `request` span per request carrying the method and URI, closed by a
+/// line with the status and latency. Server errors are logged as failures.
+pub fn trace_layer() -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>> {
+    TraceLayer::new_for_http()
+        .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
+        .on_request(DefaultOnRequest::new().level(Level::DEBUG))
+        .on_response(DefaultOnResponse::new().level(Level::INFO).latency_unit(LatencyUnit::Millis))
+}
@@ -1,26 +1,37 @@
+mod config;
+mod health;
+mod logging;
+
 use axum::{
-    extract::Multipart,
+    extract::{DefaultBodyLimit, Multipart, State},
     response::{Html, Redirect},
     routing::{get, post},
     Router,
 };
+use clap::Parser;
+use config::Config;
 use html_escape::encode_safe;
-use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
 use tokio::fs::{create_dir_all, read_to_string, write};
 use tower_http::services::ServeDir;
 
-async fn serve_index() -> Html<String> {
-    match read_to_string("index.html").await {
+async fn serve_index(State(config): State<Arc<Config>>) -> Html<String> {
+    match read_to_string(&config.paths.index).await {
         Ok(content) => Html(content),
         Err(_) => Html("<h1>Error loading page</h1>".to_string()),
     }
 }
 
-async fn handle_post(mut multipart: Multipart) -> Result<Redirect, Html<String>> {
-    let mut name = "Anonymous".to_string();
+async fn handle_post(State(config): State<Arc<Config>>, mut multipart: Multipart) -> Result<Redirect, Html<String>> {
+    let started = Instant::now();
+    let mut name = config.board.anonymous_name.clone();
     let mut subject = String::new();
     let mut message = String::new();
     let mut file_url: Option<String> = None;
+    let mut file_size = 0;
+    let mut spoiler = config.board.require_spoiler;
 
     while let Some(field) = multipart.next_field().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))? {
         let field_name = field.name().unwrap_or("").to_string();
@@ -28,18 +39,25 @@ async fn handle_post(mut multipart: Multipart) -> Result<Redirect, Html<String>>
         if field_name == "name" {
             name = field.text().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))?.trim().to_string();
             if name.is_empty() {
-                name = "Anonymous".to_string();
+                name = config.board.anonymous_name.clone();
             }
         } else if field_name == "subject" {
             subject = field.text().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))?;
         } else if field_name == "message" {
             message = field.text().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))?;
+        } else if field_name == "spoiler" {
+            spoiler = true;
         } else if field_name == "file" {
             if let Some(filename) = field.file_name() {
                 let filename = filename.to_string();
                 if !filename.is_empty() {
                     let data = field.bytes().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))?;
-                    let uploads_dir = Path::new("static/uploads");
+                    file_size = data.len();
+                    if data.len() > config.limits.max_file_size {
+                        tracing::info!(file_size, reason = "file_size", "post rejected");
+                        return Err(Html(format!("<h1>File too large (max {} KB)</h1>", config.limits.max_file_size / 1024)));
+                    }
+                    let uploads_dir = &config.paths.uploads;
                     if !uploads_dir.exists() {
                         create_dir_all(uploads_dir).await.map_err(|e| Html(format!("<h1>Error creating dir: {}</h1>", e)))?;
                     }
@@ -52,6 +70,7 @@ async fn handle_post(mut multipart: Multipart) -> Result<Redirect, Html<String>>
     }
 
     if message.trim().is_empty() {
+        tracing::info!(reason = "missing_field", "post rejected");
         return Err(Html("<h1><a href=\"/\">Message empty - Click here to return</a></h1>".to_string()));
     }
 
@@ -65,7 +84,17 @@ async fn handle_post(mut multipart: Multipart) -> Result<Redirect, Html<String>>
     );
 
     if let Some(url) = file_url {
-        snippet.push_str(&format!("<img src=\"{}\" alt=\"Uploaded image\" /><br>", url));
+        let img = format!("<img src=\"{}\" alt=\"Uploaded image\" />", url);
+        if spoiler {
+            // Clicking the spoiler reveals the image, no script needed.
+            snippet.push_str(&format!(
+                "<details class=\"spoiler\"><summary><img src=\"/static/spoiler.svg\" alt=\"Spoiler\" /></summary>{}</details><br>",
+                img
+            ));
+        } else {
+            snippet.push_str(&img);
+            snippet.push_str("<br>");
+        }
     }
 
     snippet.push_str(&format!(
@@ -74,7 +103,10 @@ async fn handle_post(mut multipart: Multipart) -> Result<Redirect, Html<String>>
     ));
 
     // Read current index.html
-    let mut content = read_to_string("index.html").await.map_err(|e| Html(format!("<h1>Error reading file: {}</h1>", e)))?;
+    let mut content = read_to_string(&config.paths.index)
+        .await
+        .inspect_err(|e| tracing::error!(path = %config.paths.index.display(), error = %e, "reading index page failed"))
+        .map_err(|e| Html(format!("<h1>Error reading file: {}</h1>", e)))?;
 
     // Insert snippet before the last <hr> before </body> to add below form but above previous posts
     if let Some(pos) = content.rfind("<hr></body>") {
@@ -82,22 +114,75 @@ async fn handle_post(mut multipart: Multipart) -> Result<Redirect, Html<String>>
     } else if let Some(pos) = content.rfind("</body>") {
         content.insert_str(pos, &snippet);
     } else {
+        tracing::error!(path = %config.paths.index.display(), "index page has no place to insert posts");
         return Err(Html("<h1>Error: Invalid HTML structure</h1>".to_string()));
     }
 
     // Write back
-    write("index.html", content).await.map_err(|e| Html(format!("<h1>Error writing file: {}</h1>", e)))?;
+    write(&config.paths.index, content)
+        .await
+        .inspect_err(|e| tracing::error!(path = %config.paths.index.display(), error = %e, "writing index page failed"))
+        .map_err(|e| Html(format!("<h1>Error writing file: {}</h1>", e)))?;
+    tracing::info!(file_size, latency_ms = started.elapsed().as_millis() as u64, "post created");
 
     Ok(Redirect::to("/"))
 }
 
+#[derive(Parser)]
+#[command(about = "Tiny imageboard that writes posts straight into its index page")]
+struct Cli {
+    /// TOML configuration file [default: config.toml, if present]
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+}
+
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("Configuration error: {}", e);
+        std::process::exit(1);
+    });
+    logging::init(&config.logging);
+    let bind = config.server.bind;
+    // Leave room for the text fields next to the largest allowed file.
+    let body_limit = config.limits.max_file_size + 1024 * 1024;
+
     let app = Router::new()
         .route("/", get(serve_index))
         .route("/post", post(handle_post))
-        .nest_service("/static", ServeDir::new("static"));
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .nest_service("/static/uploads", ServeDir::new(&config.paths.uploads))
+        .nest_service("/static", ServeDir::new(&config.paths.static_dir))
+        .layer(DefaultBodyLimit::max(body_limit))
+        .layer(logging::trace_layer())
+        .with_state(Arc::new(config));
+
+    let listener = tokio::net::TcpListener::bind(bind).await.unwrap();
+    tracing::info!(%bind, "listening");
+    axum::serve(listener, app).with_graceful_shutdown(shutdown_signal()).await.unwrap();
+    tracing::info!("exiting");
+}
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+/// Resolves on Ctrl-C or SIGTERM. The server then stops accepting
+/// connections and lets in-flight requests finish before `serve` returns.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("failed to listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("shutdown requested, draining in-flight requests");
 }
\ No newline at end of file
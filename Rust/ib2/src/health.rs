@@ -0,0 +1,50 @@
+use crate::AppState;
+use axum::{extract::State, http::StatusCode};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Liveness: the process is up and serving requests.
+pub async fn healthz() -> &'static str {
+    "ok\n"
+}
+
+/// Readiness: the index page can be opened for writing and the upload
+/// directory accepts new files. Answers 503 listing whichever check failed.
+pub async fn readyz(State(state): State<Arc<AppState>>) -> (StatusCode, String) {
+    let config = &state.config;
+    // Opening for append leaves the page untouched.
+    let index = tokio::fs::OpenOptions::new()
+        .append(true)
+        .open(&config.paths.index)
+        .await
+        .map(drop)
+        .map_err(|e| format!("{}: {}", config.paths.index.display(), e));
+    report(&[("index", index), ("uploads", writable(&config.paths.uploads).await)])
+}
+
+/// Creates and removes a probe file, which catches read-only mounts and
+/// permission changes that a metadata check would miss.
+async fn writable(dir: &Path) -> Result<(), String> {
+    static PROBES: AtomicU64 = AtomicU64::new(0);
+    let probe = dir.join(format!(".readyz-{}-{}", std::process::id(), PROBES.fetch_add(1, Ordering::Relaxed)));
+    tokio::fs::write(&probe, b"").await.map_err(|e| format!("{}: {}", dir.display(), e))?;
+    tokio::fs::remove_file(&probe).await.map_err(|e| format!("{}: {}", dir.display(), e))
+}
+
+fn report(checks: &[(&str, Result<(), String>)]) -> (StatusCode, String) {
+    let mut body = String::new();
+    let mut ready = true;
+    for (name, result) in checks {
+        match result {
+            Ok(()) => body.push_str(&format!("{}: ok\n", name)),
+            Err(e) => {
+                tracing::warn!(check = name, error = %e, "not ready");
+                body.push_str(&format!("{}: {}\n", name, e));
+                ready = false;
+            }
+        }
+    }
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, body)
+}
@@ -1,16 +1,26 @@
+mod config;
+mod health;
+mod logging;
+
 use axum::{
-    extract::{Multipart, State},
+    extract::{DefaultBodyLimit, Multipart, State},
+    http::{header, HeaderValue},
     response::{Html, Redirect},
     routing::{get, post},
     Router,
 };
 use chrono::prelude::*;
+use clap::Parser;
+use config::Config;
 use html_escape::encode_safe;
-use std::path::Path;
+use image::ImageFormat;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::fs::{create_dir_all, read_to_string, write};
 use tokio::sync::Mutex;
 use tower_http::services::ServeDir;
+use tower_http::set_header::SetResponseHeader;
 use uuid::Uuid;
 use shakmaty::{Board, Color, Role, Rank, File, Square, Chess, Position, CastlingMode, fen::Fen};
 
@@ -51,67 +61,93 @@ fn fen_to_html(board: &Board) -> String {
     html
 }
 
-async fn serve_index(State(mutex): State<Arc<Mutex<()>>>) -> Html<String> {
-    let _guard = mutex.lock().await;
-    match read_to_string("index.html").await {
+struct AppState {
+    /// Serialises the read-modify-write of the index page.
+    index_lock: Mutex<()>,
+    config: Config,
+}
+
+async fn serve_index(State(state): State<Arc<AppState>>) -> Html<String> {
+    let _guard = state.index_lock.lock().await;
+    match read_to_string(&state.config.paths.index).await {
         Ok(content) => Html(content),
         Err(_) => Html("<h1>Error loading page</h1>".to_string()),
     }
 }
 
+/// Formats every browser shows inline; other images are turned away.
+const WEB_FORMATS: [ImageFormat; 4] = [ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::Gif, ImageFormat::WebP];
+
+/// Image format of an upload going by its leading bytes, and the extension
+/// it is stored under. The client's filename and Content-Type are not trusted.
+fn detect_format(bytes: &[u8]) -> Option<(ImageFormat, &'static str)> {
+    let format = image::guess_format(bytes).ok().filter(|format| WEB_FORMATS.contains(format))?;
+    Some((format, format.extensions_str().first()?))
+}
+
 async fn handle_post(
-    State(mutex): State<Arc<Mutex<()>>>,
+    State(state): State<Arc<AppState>>,
     mut multipart: Multipart,
 ) -> Result<Redirect, Html<String>> {
-    let mut name = "Anonymous".to_string();
+    let started = Instant::now();
+    let config = &state.config;
+    let mut name = config.board.anonymous_name.clone();
     let mut subject = String::new();
     let mut message = String::new();
     let mut fen = String::new();
     let mut file_url: Option<String> = None;
+    let mut file_size = 0;
+    let mut spoiler = config.board.require_spoiler;
 
     while let Some(field) = multipart.next_field().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))? {
         let field_name = field.name().unwrap_or("").to_string();
         if field_name == "name" {
             name = field.text().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))?.trim().to_string();
             if name.is_empty() {
-                name = "Anonymous".to_string();
+                name = config.board.anonymous_name.clone();
             }
         } else if field_name == "subject" {
             subject = field.text().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))?;
         } else if field_name == "message" {
             message = field.text().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))?;
+        } else if field_name == "spoiler" {
+            spoiler = true;
         } else if field_name == "fen" {
             fen = field.text().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))?;
-        } else if field_name == "file" {
-            if let Some(filename) = field.file_name() {
-                let filename = filename.to_string();
-                if !filename.is_empty() {
-                    let content_type = field.content_type().unwrap_or("").to_string();
-                    if content_type.starts_with("image/") {
-                        let data = field.bytes().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))?;
-                        if data.len() > 5 * 1024 * 1024 {
-                            return Err(Html("<h1>File too large (max 5MB)</h1>".to_string()));
-                        }
-                        let ext = Path::new(&filename).extension().and_then(|os| os.to_str()).unwrap_or("");
-                        if ext.is_empty() {
-                            continue;
-                        }
-                        let uuid_str = Uuid::new_v4().simple().to_string();
-                        let new_filename = format!("{}.{}", uuid_str, ext);
-                        let uploads_dir = Path::new("static/uploads");
-                        if !uploads_dir.exists() {
-                            create_dir_all(uploads_dir).await.map_err(|e| Html(format!("<h1>Error creating dir: {}</h1>", e)))?;
-                        }
-                        let path = uploads_dir.join(&new_filename);
-                        write(&path, &data).await.map_err(|e| Html(format!("<h1>Error writing file: {}</h1>", e)))?;
-                        file_url = Some(format!("/static/uploads/{}", new_filename));
-                    }
+        } else if field_name == "file" && let Some(filename) = field.file_name() {
+            let filename = filename.to_string();
+            if !filename.is_empty() {
+                let data = field.bytes().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))?;
+                file_size = data.len();
+                if data.len() > config.limits.max_file_size {
+                    tracing::info!(file_size, reason = "file_size", "post rejected");
+                    return Err(Html(format!("<h1>File too large (max {} KB)</h1>", config.limits.max_file_size / 1024)));
+                }
+                // The bytes decide the format; the extension only has to agree.
+                let Some((format, stored_ext)) = detect_format(&data) else {
+                    tracing::info!(file_size, reason = "file_type", "post rejected");
+                    return Err(Html("<h1>Only JPEG, PNG, GIF and WebP images can be uploaded</h1>".to_string()));
+                };
+                let ext = Path::new(&filename).extension().and_then(|os| os.to_str()).unwrap_or("").to_lowercase();
+                if !ext.is_empty() && ImageFormat::from_extension(&ext) != Some(format) {
+                    tracing::info!(file_size, reason = "file_mismatch", "post rejected");
+                    return Err(Html(format!("<h1>File contents are not a .{} image</h1>", ext)));
                 }
+                let uuid_str = Uuid::new_v4().simple().to_string();
+                let new_filename = format!("{}.{}", uuid_str, stored_ext);
+                let uploads_dir = &config.paths.uploads;
+                if !uploads_dir.exists() {
+                    create_dir_all(uploads_dir).await.map_err(|e| Html(format!("<h1>Error creating dir: {}</h1>", e)))?;
+                }
+                let path = uploads_dir.join(&new_filename);
+                write(&path, &data).await.map_err(|e| Html(format!("<h1>Error writing file: {}</h1>", e)))?;
+                file_url = Some(format!("/static/uploads/{}", new_filename));
             }
         }
     }
 
     if message.trim().is_empty() {
+        tracing::info!(reason = "missing_field", "post rejected");
         return Err(Html("<h1><a href=\"/\">Message empty - Click here to return</a></h1>".to_string()));
     }
 
@@ -127,44 +163,113 @@ async fn handle_post(
         escaped_name, escaped_subject, escaped_date
     );
     if let Some(url) = file_url {
-        snippet.push_str(&format!("<img src=\"{}\" alt=\"Uploaded image\" /><br>", url));
-    }
-    if !fen.trim().is_empty() {
-        if let Ok(fen_obj) = Fen::from_ascii(fen.as_bytes()) {
-            if let Ok(pos) = fen_obj.into_position::<Chess>(CastlingMode::Standard) {
-                let board_html = fen_to_html(&pos.board());
-                snippet.push_str(&format!("<div class=\"diagram\">{}</div><br>", board_html));
-            }
+        let img = format!("<img src=\"{}\" alt=\"Uploaded image\" />", url);
+        if spoiler {
+            // Clicking the spoiler reveals the image, no script needed.
+            snippet.push_str(&format!(
+                "<details class=\"spoiler\"><summary><img src=\"/static/spoiler.svg\" alt=\"Spoiler\" /></summary>{}</details><br>",
+                img
+            ));
+        } else {
+            snippet.push_str(&img);
+            snippet.push_str("<br>");
         }
     }
+    if config.features.diagrams
+        && !fen.trim().is_empty()
+        && let Ok(fen_obj) = Fen::from_ascii(fen.as_bytes())
+        && let Ok(pos) = fen_obj.into_position::<Chess>(CastlingMode::Standard)
+    {
+        let board_html = fen_to_html(pos.board());
+        snippet.push_str(&format!("<div class=\"diagram\">{}</div><br>", board_html));
+    }
     snippet.push_str(&format!(
         "<div class=\"message\">{}</div></td></tr></table>",
         escaped_message
     ));
 
-    let _guard = mutex.lock().await;
-    let mut content = read_to_string("index.html").await.map_err(|e| Html(format!("<h1>Error reading file: {}</h1>", e)))?;
+    let _guard = state.index_lock.lock().await;
+    let mut content = read_to_string(&config.paths.index)
+        .await
+        .inspect_err(|e| tracing::error!(path = %config.paths.index.display(), error = %e, "reading index page failed"))
+        .map_err(|e| Html(format!("<h1>Error reading file: {}</h1>", e)))?;
     if let Some(pos) = content.rfind("</form>") {
         let insert_pos = pos + 7;
         content.insert_str(insert_pos, &snippet);
     } else {
+        tracing::error!(path = %config.paths.index.display(), "index page has no place to insert posts");
         return Err(Html("<h1>Error: Invalid HTML structure</h1>".to_string()));
     }
-    write("index.html", content).await.map_err(|e| Html(format!("<h1>Error writing file: {}</h1>", e)))?;
+    write(&config.paths.index, content)
+        .await
+        .inspect_err(|e| tracing::error!(path = %config.paths.index.display(), error = %e, "writing index page failed"))
+        .map_err(|e| Html(format!("<h1>Error writing file: {}</h1>", e)))?;
+    tracing::info!(file_size, latency_ms = started.elapsed().as_millis() as u64, "post created");
 
     Ok(Redirect::to("/"))
 }
 
+#[derive(Parser)]
+#[command(about = "Chess imageboard that writes posts straight into its index page")]
+struct Cli {
+    /// TOML configuration file [default: config.toml, if present]
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+}
+
 #[tokio::main]
 async fn main() {
-    let index_mutex = Arc::new(Mutex::new(()));
+    let cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("Configuration error: {}", e);
+        std::process::exit(1);
+    });
+    logging::init(&config.logging);
+    let bind = config.server.bind;
+    // Leave room for the text fields next to the largest allowed file.
+    let body_limit = config.limits.max_file_size + 1024 * 1024;
 
     let app = Router::new()
         .route("/", get(serve_index))
         .route("/post", post(handle_post))
-        .nest_service("/static", ServeDir::new("static"))
-        .with_state(index_mutex);
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .nest_service("/static/uploads", no_sniff(ServeDir::new(&config.paths.uploads)))
+        .nest_service("/static", ServeDir::new(&config.paths.static_dir))
+        .layer(DefaultBodyLimit::max(body_limit))
+        .layer(logging::trace_layer())
+        .with_state(Arc::new(AppState { index_lock: Mutex::new(()), config }));
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let listener = tokio::net::TcpListener::bind(bind).await.unwrap();
+    tracing::info!(%bind, "listening");
+    axum::serve(listener, app).with_graceful_shutdown(shutdown_signal()).await.unwrap();
+    tracing::info!("exiting");
+}
+
+/// Uploads are served with the type their extension implies; `nosniff`
+/// stops browsers from guessing another one, such as HTML, from the content.
+fn no_sniff<S>(service: S) -> SetResponseHeader<S, HeaderValue> {
+    SetResponseHeader::overriding(service, header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"))
+}
+
+/// Resolves on Ctrl-C or SIGTERM. The server then stops accepting
+/// connections and lets in-flight requests finish before `serve` returns.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("failed to listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("shutdown requested, draining in-flight requests");
 }
\ No newline at end of file
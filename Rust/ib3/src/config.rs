@@ -0,0 +1,312 @@
+use serde::Deserialize;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Read when no `--config` flag is given and the file exists.
+pub const DEFAULT_PATH: &str = "config.toml";
+
+/// Everything that used to be hard-coded. Every key is optional; see
+/// `config.example.toml` for the defaults and the `IB_*` overrides.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub board: BoardConfig,
+    pub limits: LimitsConfig,
+    pub paths: PathsConfig,
+    pub storage: StorageConfig,
+    pub features: FeaturesConfig,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: SocketAddr,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BoardConfig {
+    pub title: String,
+    pub subtitle: String,
+    pub anonymous_name: String,
+    /// Turn away files that were already posted.
+    pub reject_duplicate_files: bool,
+    /// Hide every file behind a spoiler, whatever the poster chose.
+    pub require_spoiler: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_file_size: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    pub base_html: PathBuf,
+    pub database: PathBuf,
+    pub static_dir: PathBuf,
+    pub uploads: PathBuf,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    pub s3: S3Config,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// `paths.uploads`.
+    #[default]
+    Local,
+    /// An S3 bucket, on AWS or any compatible server such as MinIO.
+    S3,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+    /// Base URL of an S3-compatible server; AWS when unset.
+    pub endpoint: Option<String>,
+    pub bucket: String,
+    pub region: String,
+    /// Taken from the usual `AWS_*` environment variables when unset.
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeaturesConfig {
+    pub pgn: bool,
+    pub eco: bool,
+    pub position_search: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// `tracing` filter directives, e.g. `info` or `info,tower_http=debug`.
+    pub level: String,
+    pub format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One line per event.
+    Text,
+    /// Multi-line and indented, for reading in a terminal.
+    Pretty,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig { bind: SocketAddr::from(([127, 0, 0, 1], 3000)) }
+    }
+}
+
+impl Default for BoardConfig {
+    fn default() -> Self {
+        BoardConfig {
+            title: "ChessIB".to_string(),
+            subtitle: "General chess discussion, puzzles, and diagrams".to_string(),
+            anonymous_name: "Anonymous".to_string(),
+            reject_duplicate_files: false,
+            require_spoiler: false,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig { max_file_size: 5 * 1024 * 1024 }
+    }
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        PathsConfig {
+            base_html: PathBuf::from("base.html"),
+            database: PathBuf::from("chess_ib_db"),
+            static_dir: PathBuf::from("static"),
+            uploads: PathBuf::from("static/uploads"),
+        }
+    }
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        S3Config {
+            endpoint: None,
+            bucket: String::new(),
+            region: "us-east-1".to_string(),
+            access_key_id: None,
+            secret_access_key: None,
+        }
+    }
+}
+
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        FeaturesConfig { pgn: true, eco: true, position_search: true }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig { level: "info".to_string(), format: LogFormat::Text }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("expected text, pretty or json".to_string()),
+        }
+    }
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(StorageBackend::Local),
+            "s3" => Ok(StorageBackend::S3),
+            _ => Err("expected local or s3".to_string()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Env { var: &'static str, value: String, reason: String },
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "{} is not valid: {}", path.display(), e),
+            ConfigError::Env { var, value, reason } => write!(f, "environment variable {}={:?}: {}", var, value, reason),
+            ConfigError::Invalid(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl Config {
+    /// Reads `path` (or `config.toml` if present), applies environment
+    /// overrides and validates the result.
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        let mut config = match path {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_PATH).exists() => Self::from_file(Path::new(DEFAULT_PATH))?,
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        env_override("IB_BIND", &mut self.server.bind)?;
+        env_override("IB_TITLE", &mut self.board.title)?;
+        env_override("IB_SUBTITLE", &mut self.board.subtitle)?;
+        env_override("IB_ANONYMOUS_NAME", &mut self.board.anonymous_name)?;
+        env_override("IB_REJECT_DUPLICATE_FILES", &mut self.board.reject_duplicate_files)?;
+        env_override("IB_REQUIRE_SPOILER", &mut self.board.require_spoiler)?;
+        env_override("IB_MAX_FILE_SIZE", &mut self.limits.max_file_size)?;
+        env_override("IB_BASE_HTML", &mut self.paths.base_html)?;
+        env_override("IB_DATABASE", &mut self.paths.database)?;
+        env_override("IB_STATIC_DIR", &mut self.paths.static_dir)?;
+        env_override("IB_UPLOADS_DIR", &mut self.paths.uploads)?;
+        env_override("IB_STORAGE", &mut self.storage.backend)?;
+        if let Ok(endpoint) = std::env::var("IB_S3_ENDPOINT") {
+            self.storage.s3.endpoint = Some(endpoint);
+        }
+        env_override("IB_S3_BUCKET", &mut self.storage.s3.bucket)?;
+        env_override("IB_S3_REGION", &mut self.storage.s3.region)?;
+        if let Ok(key) = std::env::var("IB_S3_ACCESS_KEY_ID") {
+            self.storage.s3.access_key_id = Some(key);
+        }
+        if let Ok(secret) = std::env::var("IB_S3_SECRET_ACCESS_KEY") {
+            self.storage.s3.secret_access_key = Some(secret);
+        }
+        env_override("IB_PGN", &mut self.features.pgn)?;
+        env_override("IB_ECO", &mut self.features.eco)?;
+        env_override("IB_POSITION_SEARCH", &mut self.features.position_search)?;
+        env_override("IB_LOG", &mut self.logging.level)?;
+        env_override("IB_LOG_FORMAT", &mut self.logging.format)?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.logging.level) {
+            return invalid(format!("logging.level: {}", e));
+        }
+        if self.board.title.trim().is_empty() {
+            return invalid("board.title must not be empty".to_string());
+        }
+        if self.board.anonymous_name.trim().is_empty() {
+            return invalid("board.anonymous_name must not be empty".to_string());
+        }
+        if self.limits.max_file_size == 0 {
+            return invalid("limits.max_file_size must be positive".to_string());
+        }
+        if !self.paths.base_html.is_file() {
+            return invalid(format!("paths.base_html: {} does not exist", self.paths.base_html.display()));
+        }
+        if !self.paths.static_dir.is_dir() {
+            return invalid(format!("paths.static_dir: {} is not a directory", self.paths.static_dir.display()));
+        }
+        if self.storage.backend == StorageBackend::Local && self.paths.uploads == self.paths.static_dir {
+            return invalid("paths.uploads must be a directory of its own, not paths.static_dir".to_string());
+        }
+        match self.storage.backend {
+            StorageBackend::Local => std::fs::create_dir_all(&self.paths.uploads).or_else(|e| {
+                invalid(format!("paths.uploads: cannot create {}: {}", self.paths.uploads.display(), e))
+            })?,
+            StorageBackend::S3 if self.storage.s3.bucket.is_empty() => {
+                return invalid("storage.s3.bucket must be set for the s3 backend".to_string());
+            }
+            StorageBackend::S3 => {}
+        }
+        Ok(())
+    }
+}
+
+fn env_override<T>(var: &'static str, target: &mut T) -> Result<(), ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    if let Ok(value) = std::env::var(var) {
+        *target = value.parse().map_err(|e: T::Err| ConfigError::Env { var, value, reason: e.to_string() })?;
+    }
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use axum::{
+    extract::FromRequestParts,
+    http::{header, request::Parts},
+};
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use chrono_tz::Tz;
+use std::convert::Infallible;
+
+/// Cookie holding the reader's IANA time zone; `/static/timezone.js` sets it
+/// from the browser.
+const ZONE_COOKIE: &str = "tz";
+
+/// Who is looking at the page: their time zone, and one "now" so every
+/// relative time on a page is measured from the same instant.
+pub struct Viewer {
+    zone: Tz,
+    now: DateTime<Utc>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Viewer {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // A missing or unknown zone falls back to UTC rather than failing the page.
+        let zone = parts
+            .headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(name, _)| *name == ZONE_COOKIE)
+            .and_then(|(_, value)| value.parse().ok())
+            .unwrap_or(Tz::UTC);
+        Ok(Viewer { zone, now: Utc::now() })
+    }
+}
+
+impl Viewer {
+    /// `<time>` element carrying the UTC instant for machines, and the local
+    /// time plus how long ago it was for people.
+    pub fn time(&self, at: DateTime<Utc>) -> String {
+        let local = at.with_timezone(&self.zone);
+        format!(
+            r#"<time datetime="{}" title="{}">{} ({})</time>"#,
+            at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            local.format("%Y-%m-%d %H:%M:%S %Z"),
+            local.format("%Y-%m-%d %H:%M:%S"),
+            ago(self.now - at)
+        )
+    }
+}
+
+/// "3 hours ago" style wording, rounded down to the largest whole unit.
+fn ago(elapsed: TimeDelta) -> String {
+    let minutes = elapsed.num_minutes();
+    let (count, unit) = match minutes {
+        ..=0 => return "just now".to_string(),
+        1..=59 => (minutes, "minute"),
+        60..=1439 => (minutes / 60, "hour"),
+        1440..=43199 => (minutes / 1440, "day"),
+        43200..=525599 => (minutes / 43200, "month"),
+        _ => (minutes / 525600, "year"),
+    };
+    format!("{} {}{} ago", count, unit, if count == 1 { "" } else { "s" })
+}
@@ -0,0 +1,68 @@
+use crate::{pgn, positions};
+use serde::{Deserialize, Serialize};
+use shakmaty::Chess;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// Opening table in the same `eco<TAB>name<TAB>pgn` layout as the lichess
+/// chess-openings dataset, compiled into the binary.
+const ECO_TSV: &str = include_str!("../eco.tsv");
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Opening {
+    pub eco: String,
+    pub name: String,
+}
+
+/// Every table line is replayed once and indexed by the Zobrist hash of its
+/// final position, so transpositions land on the same entry.
+static OPENINGS: LazyLock<HashMap<u64, Opening>> = LazyLock::new(|| {
+    let mut map = HashMap::new();
+    for (i, line) in ECO_TSV.lines().enumerate().skip(1) {
+        let mut cols = line.split('\t');
+        let (Some(eco), Some(name), Some(moves)) = (cols.next(), cols.next(), cols.next()) else {
+            continue;
+        };
+        let reached = pgn::positions(moves)
+            .unwrap_or_else(|e| panic!("eco.tsv line {}: {}", i + 1, e));
+        let last = reached.last().expect("positions is never empty");
+        map.insert(positions::hash(last), Opening { eco: eco.to_string(), name: name.to_string() });
+    }
+    map
+});
+
+/// Looks up a single position, e.g. a posted FEN.
+pub fn classify_position(pos: &Chess) -> Option<Opening> {
+    OPENINGS.get(&positions::hash(pos)).cloned()
+}
+
+/// Returns the deepest known opening reached during a game.
+pub fn classify_game(positions: &[Chess]) -> Option<Opening> {
+    positions.iter().rev().find_map(classify_position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Forces the whole table, so a bad `eco.tsv` row fails here rather than
+    /// on the first request.
+    #[test]
+    fn table_loads() {
+        assert!(OPENINGS.len() > 100);
+    }
+
+    #[test]
+    fn games_get_their_deepest_opening() {
+        let opening = classify_game(&pgn::positions("1. e4 c5").unwrap()).unwrap();
+        assert_eq!((opening.eco.as_str(), opening.name.as_str()), ("B20", "Sicilian Defense"));
+        let opening = classify_game(&pgn::positions("1. e4 c5 2. Bc4 a6 3. a4").unwrap()).unwrap();
+        assert_eq!(opening.name, "Sicilian Defense: Bowdler Attack");
+    }
+
+    #[test]
+    fn transpositions_find_the_same_opening() {
+        let transposed = classify_game(&pgn::positions("1. c4 e6 2. d4 Nf6").unwrap()).unwrap();
+        assert_eq!(transposed.eco, "E00");
+    }
+}
@@ -0,0 +1,191 @@
+use crate::error::AppResult;
+use crate::media::Container;
+use crate::storage::Storage;
+use axum::extract::multipart::Field;
+use axum::http::StatusCode;
+use bincode::{deserialize, serialize};
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sled::Db;
+use std::path::Path;
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+/// Leading bytes of an upload kept in memory, enough to tell its format.
+const HEAD_LEN: usize = 64;
+
+/// Value in the `files` tree, keyed by the SHA-256 of the content: where
+/// the single stored copy lives and how many posts use it.
+#[derive(Serialize, Deserialize)]
+pub struct FileEntry {
+    pub file_url: String,
+    pub refs: u64,
+}
+
+/// Formats every browser shows inline; other images are turned away.
+const WEB_FORMATS: [ImageFormat; 4] = [ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::Gif, ImageFormat::WebP];
+
+/// What an upload is, going by its leading bytes. The client's filename
+/// and Content-Type are not trusted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Image(ImageFormat),
+    Media(Container),
+}
+
+impl Format {
+    pub fn detect(bytes: &[u8]) -> Option<Format> {
+        match image::guess_format(bytes) {
+            Ok(format) => WEB_FORMATS.contains(&format).then_some(Format::Image(format)),
+            Err(_) => Container::sniff(bytes).map(Format::Media),
+        }
+    }
+
+    /// Whether `ext`, as claimed by the client, names this format.
+    pub fn has_extension(self, ext: &str) -> bool {
+        match self {
+            Format::Image(format) => ImageFormat::from_extension(ext) == Some(format),
+            Format::Media(container) => Container::from_extension(ext) == Some(container),
+        }
+    }
+
+    /// Extension the file is stored under.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Image(format) => format.extensions_str().first().copied().unwrap_or("bin"),
+            Format::Media(container) => container.extension(),
+        }
+    }
+}
+
+pub fn sha256(bytes: &[u8]) -> [u8; 32] {
+    Sha256::digest(bytes).into()
+}
+
+/// An upload streamed to a temporary file, which is removed when this is
+/// dropped.
+pub struct Spooled {
+    file: NamedTempFile,
+    pub size: usize,
+    pub sha256: [u8; 32],
+    /// The first `HEAD_LEN` bytes.
+    pub head: Vec<u8>,
+}
+
+impl Spooled {
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+}
+
+/// Streams `field` to a temporary file chunk by chunk, hashing it on the
+/// way. `None` means it held more than `limit` bytes: nothing past the
+/// limit is written, and the rest is read and dropped, up to the route's
+/// body limit, so the client is still listening for the answer.
+pub async fn spool(field: &mut Field<'_>, limit: usize) -> AppResult<Option<Spooled>> {
+    let file = NamedTempFile::new()?;
+    let mut writer = tokio::fs::File::from_std(file.reopen()?);
+    let mut size = 0;
+    let mut hasher = Sha256::new();
+    let mut head = Vec::with_capacity(HEAD_LEN);
+    loop {
+        let chunk = match field.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            // The body limit cut the request off inside this file.
+            Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        size += chunk.len();
+        if size > limit {
+            continue;
+        }
+        hasher.update(&chunk);
+        let wanted = HEAD_LEN.saturating_sub(head.len()).min(chunk.len());
+        head.extend_from_slice(&chunk[..wanted]);
+        writer.write_all(&chunk).await?;
+    }
+    if size > limit {
+        return Ok(None);
+    }
+    writer.flush().await?;
+    Ok(Some(Spooled { file, size, sha256: hasher.finalize().into(), head }))
+}
+
+/// True if some post already carries this content.
+pub fn is_posted(db: &Db, sha256: &[u8; 32]) -> AppResult<bool> {
+    match db.open_tree("files")?.get(sha256)? {
+        Some(value) => Ok(deserialize::<FileEntry>(&value)?.refs > 0),
+        None => Ok(false),
+    }
+}
+
+/// Takes a reference to the stored copy of the upload at `path`, writing it
+/// to storage only if the content is new, and returns its URL.
+pub async fn acquire(db: &Db, storage: &dyn Storage, path: &Path, sha256: &[u8; 32], ext: &str) -> AppResult<String> {
+    let files = db.open_tree("files")?;
+    let mut written: Option<String> = None;
+    // Compare-and-swap retries until no other post changed the entry in between.
+    let file_url = loop {
+        let current = files.get(sha256)?;
+        let entry = match &current {
+            Some(value) => {
+                let mut entry: FileEntry = deserialize(value)?;
+                entry.refs += 1;
+                entry
+            }
+            None => {
+                let file_url = match &written {
+                    Some(file_url) => file_url.clone(),
+                    None => {
+                        let filename = format!("{}.{}", Uuid::new_v4().simple(), ext);
+                        storage.put_file(&filename, path).await?;
+                        let file_url = format!("/static/uploads/{}", filename);
+                        written = Some(file_url.clone());
+                        file_url
+                    }
+                };
+                FileEntry { file_url, refs: 1 }
+            }
+        };
+        if files.compare_and_swap(sha256, current, Some(serialize(&entry)?))?.is_ok() {
+            break entry.file_url;
+        }
+    };
+    // Another post stored the same content first; its copy is used instead.
+    if let Some(unused) = written.filter(|url| *url != file_url) {
+        remove(storage, &unused).await;
+    }
+    Ok(file_url)
+}
+
+/// Gives back a reference taken by `acquire`, deleting the stored copy when
+/// no post uses it any more.
+pub async fn release(db: &Db, storage: &dyn Storage, sha256: &[u8; 32]) -> AppResult<()> {
+    let files = db.open_tree("files")?;
+    loop {
+        let Some(current) = files.get(sha256)? else {
+            return Ok(());
+        };
+        let mut entry: FileEntry = deserialize(&current)?;
+        entry.refs = entry.refs.saturating_sub(1);
+        let new = if entry.refs == 0 { None } else { Some(serialize(&entry)?) };
+        if files.compare_and_swap(sha256, Some(current), new)?.is_ok() {
+            if entry.refs == 0 {
+                remove(storage, &entry.file_url).await;
+            }
+            return Ok(());
+        }
+    }
+}
+
+async fn remove(storage: &dyn Storage, file_url: &str) {
+    let Some(filename) = file_url.strip_prefix("/static/uploads/") else {
+        return;
+    };
+    if let Err(e) = storage.delete(filename).await {
+        tracing::warn!(filename, error = %e, "could not remove upload");
+    }
+}
@@ -0,0 +1,58 @@
+use crate::AppState;
+use crate::storage::Storage;
+use axum::{extract::State, http::StatusCode};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Liveness: the process is up and serving requests.
+pub async fn healthz() -> &'static str {
+    "ok\n"
+}
+
+/// Readiness: sled answers reads, the page template is readable and upload
+/// storage accepts new files. Answers 503 listing whichever check
+/// failed.
+pub async fn readyz(State(state): State<Arc<AppState>>) -> (StatusCode, String) {
+    let database = state
+        .db
+        .open_tree("posts")
+        .and_then(|tree| tree.first())
+        .map(drop)
+        .map_err(|e| e.to_string());
+    let template = tokio::fs::File::open(&state.config.paths.base_html)
+        .await
+        .map(drop)
+        .map_err(|e| format!("{}: {}", state.config.paths.base_html.display(), e));
+    report(&[
+        ("database", database),
+        ("template", template),
+        ("uploads", writable(state.storage.as_ref()).await),
+    ])
+}
+
+/// Creates and removes a probe file, which catches read-only mounts,
+/// permission changes and unreachable buckets that a metadata check would
+/// miss.
+async fn writable(storage: &dyn Storage) -> Result<(), String> {
+    static PROBES: AtomicU64 = AtomicU64::new(0);
+    let probe = format!(".readyz-{}-{}", std::process::id(), PROBES.fetch_add(1, Ordering::Relaxed));
+    storage.put(&probe, Vec::new()).await.map_err(|e| e.to_string())?;
+    storage.delete(&probe).await.map_err(|e| e.to_string())
+}
+
+fn report(checks: &[(&str, Result<(), String>)]) -> (StatusCode, String) {
+    let mut body = String::new();
+    let mut ready = true;
+    for (name, result) in checks {
+        match result {
+            Ok(()) => body.push_str(&format!("{}: ok\n", name)),
+            Err(e) => {
+                tracing::warn!(check = name, error = %e, "not ready");
+                body.push_str(&format!("{}: {}\n", name, e));
+                ready = false;
+            }
+        }
+    }
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, body)
+}
@@ -1,29 +1,76 @@
+mod config;
+mod dates;
+mod eco;
+mod error;
+mod files;
+mod health;
+mod logging;
+mod media;
+mod migrate;
+mod pgn;
+mod positions;
+mod storage;
+#[cfg(test)]
+mod tests;
+mod validate;
+
 use axum::{
-    extract::{Multipart, State},
-    response::{Html, Redirect},
+    extract::{DefaultBodyLimit, Multipart, Path as UrlPath, Query, Request, State},
+    http::{header, HeaderValue},
+    response::{Html, Redirect, Response},
     routing::{get, post},
     Router,
 };
-use chrono::prelude::*;
+use chrono::{DateTime, TimeDelta, Utc};
+use clap::Parser;
 use html_escape::encode_safe;
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::fs::{create_dir_all, read_to_string, write};
+use std::time::Instant;
+use tokio::fs::read_to_string;
 use tower_http::services::ServeDir;
-use uuid::Uuid;
 use shakmaty::{Board, Color, Role, Rank, File, Square, Chess, Position, CastlingMode, fen::Fen};
-use sled::{Db, IVec};
+use sled::Db;
+use files::Format;
+use media::Container;
 use serde::{Deserialize, Serialize};
 use bincode::{serialize, deserialize};
+use config::Config;
+use dates::Viewer;
+use eco::Opening;
+use error::{AppError, AppResult};
+use storage::Storage;
 
 #[derive(Serialize, Deserialize, Clone)]
 struct Post {
     name: String,
     subject: String,
     message: String,
-    date: String,
+    created_at: DateTime<Utc>,
     fen: Option<String>,
     file_url: Option<String>,
+    pgn: Option<String>,
+    opening: Option<Opening>,
+    /// The file is hidden behind a spoiler image until clicked.
+    spoiler: bool,
+}
+
+/// Posts are keyed by creation time in microseconds, big endian, so sled's
+/// key order is posting order.
+fn post_key(created_at: DateTime<Utc>) -> [u8; 8] {
+    (created_at.timestamp_micros() as u64).to_be_bytes()
+}
+
+fn decode_post(bytes: &[u8]) -> Option<Post> {
+    deserialize(bytes).ok()
+}
+
+fn parse_fen(fen: &str) -> Option<Chess> {
+    Fen::from_ascii(fen.trim().as_bytes())
+        .ok()?
+        .into_position::<Chess>(CastlingMode::Standard)
+        .ok()
 }
 
 fn get_unicode(color: Color, role: Role) -> String {
@@ -63,142 +110,453 @@ fn fen_to_html(board: &Board) -> String {
     html
 }
 
-async fn serve_index(State(state): State<Arc<AppState>>) -> Html<String> {
-    let base_html = match read_to_string("base.html").await {
-        Ok(content) => content,
-        Err(_) => return Html("<h1>Error loading page</h1>".to_string()),
-    };
+fn render_post(post: Post, viewer: &Viewer) -> String {
+    let escaped_name = encode_safe(&post.name).to_string();
+    let escaped_subject = encode_safe(&post.subject).to_string();
+    let escaped_message = encode_safe(&post.message).to_string().replace("\n", "<br>");
 
-    let mut posts_html = String::new();
-    let tree = state.db.open_tree("posts").unwrap();
-    let mut posts: Vec<(IVec, IVec)> = tree.iter().map(|res| res.unwrap()).collect();
-    posts.sort_by_key(|(key, _)| key.clone()); // Assuming keys are sortable, e.g., timestamps as strings
-
-    for (_, value) in posts.iter().rev() { // Reverse to show newest first
-        let post: Post = deserialize(value).unwrap();
-        let escaped_name = encode_safe(&post.name).to_string();
-        let escaped_subject = encode_safe(&post.subject).to_string();
-        let escaped_message = encode_safe(&post.message).to_string().replace("\n", "<br>");
-        let escaped_date = encode_safe(&post.date).to_string();
-
-        let mut snippet = format!(
-            "<hr><table class=\"post-table\"><tr><td class=\"post\"><div class=\"name\"><b>{}</b></div><div class=\"subject\"><b>{}</b></div><div class=\"date\">{}</div>",
-            escaped_name, escaped_subject, escaped_date
-        );
-        if let Some(url) = post.file_url {
-            snippet.push_str(&format!("<img src=\"{}\" alt=\"Uploaded image\" /><br>", url));
-        }
-        if let Some(fen_str) = post.fen {
-            if let Ok(fen_obj) = Fen::from_ascii(fen_str.as_bytes()) {
-                if let Ok(pos) = fen_obj.into_position::<Chess>(CastlingMode::Standard) {
-                    let board_html = fen_to_html(&pos.board());
-                    snippet.push_str(&format!("<div class=\"diagram\">{}</div><br>", board_html));
-                }
+    let mut snippet = format!(
+        "<hr><table class=\"post-table\"><tr><td class=\"post\"><div class=\"name\"><b>{}</b></div><div class=\"subject\"><b>{}</b></div><div class=\"date\">{}</div>",
+        escaped_name,
+        escaped_subject,
+        viewer.time(post.created_at)
+    );
+    if let Some(opening) = &post.opening {
+        snippet.push_str(&format!(
+            "<div class=\"opening\"><a href=\"/?eco={}\">{}</a> {}</div>",
+            encode_safe(&opening.eco),
+            encode_safe(&opening.eco),
+            encode_safe(&opening.name)
+        ));
+    }
+    if let Some(url) = post.file_url {
+        // Stored names end in the extension of the sniffed format.
+        let container = url.rsplit_once('.').and_then(|(_, ext)| Container::from_extension(ext));
+        let tag = match container {
+            Some(container) if container.is_video() => {
+                format!("<video src=\"{}\" controls preload=\"metadata\"></video>", url)
             }
+            Some(_) => format!("<audio src=\"{}\" controls preload=\"metadata\"></audio>", url),
+            None => format!("<img src=\"{}\" alt=\"Uploaded image\" />", url),
+        };
+        if post.spoiler {
+            snippet.push_str(&format!(
+                "<details class=\"spoiler\"><summary><img src=\"/static/spoiler.svg\" alt=\"Spoiler\" /></summary>{}</details>",
+                tag
+            ));
+        } else {
+            snippet.push_str(&tag);
         }
-        snippet.push_str(&format!(
-            "<div class=\"message\">{}</div></td></tr></table>",
-            escaped_message
+        snippet.push_str("<br>");
+    }
+    if let Some(pos) = post.fen.as_deref().and_then(parse_fen) {
+        let board_html = fen_to_html(pos.board());
+        snippet.push_str(&format!("<div class=\"diagram\">{}</div><br>", board_html));
+    } else if let Some(pos) = post.pgn.as_deref().and_then(|pgn| pgn::positions(pgn).ok()?.pop()) {
+        let board_html = fen_to_html(pos.board());
+        snippet.push_str(&format!("<div class=\"diagram\">{}</div><br>", board_html));
+    }
+    if let Some(pgn) = &post.pgn {
+        snippet.push_str(&format!("<pre class=\"pgn\">{}</pre>", encode_safe(pgn)));
+    }
+    snippet.push_str(&format!(
+        "<div class=\"message\">{}</div></td></tr></table>",
+        escaped_message
+    ));
+    snippet
+}
+
+/// Reads the page template and fills in the configured board details and
+/// optional sections; posts still go in `<!-- POSTS -->`.
+async fn load_page(config: &Config) -> std::io::Result<String> {
+    let html = read_to_string(&config.paths.base_html).await?;
+    let html = html
+        .replace("<!-- TITLE -->", &encode_safe(&config.board.title))
+        .replace("<!-- SUBTITLE -->", &encode_safe(&config.board.subtitle))
+        .replace("<!-- ANONYMOUS_NAME -->", &encode_safe(&config.board.anonymous_name));
+    let html = toggle_section(html, "pgn", config.features.pgn);
+    let html = toggle_section(html, "eco", config.features.eco);
+    let html = toggle_section(html, "spoiler_choice", !config.board.require_spoiler);
+    let html = toggle_section(html, "spoiler_required", config.board.require_spoiler);
+    Ok(toggle_section(html, "position_search", config.features.position_search))
+}
+
+/// Keeps or drops everything between `<!-- IF name -->` and `<!-- END name -->`.
+fn toggle_section(mut html: String, name: &str, enabled: bool) -> String {
+    let start = format!("<!-- IF {} -->", name);
+    let end = format!("<!-- END {} -->", name);
+    if enabled {
+        return html.replace(&start, "").replace(&end, "");
+    }
+    while let Some(from) = html.find(&start) {
+        let Some(len) = html[from..].find(&end) else {
+            break;
+        };
+        html.replace_range(from..from + len + end.len(), "");
+    }
+    html
+}
+
+async fn serve_index(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+    viewer: Viewer,
+) -> AppResult<Html<String>> {
+    let base_html = load_page(&state.config).await?;
+
+    let eco_filter = query
+        .get("eco")
+        .filter(|_| state.config.features.eco)
+        .map(|eco| eco.trim().to_ascii_uppercase())
+        .filter(|eco| !eco.is_empty());
+
+    let mut posts_html = String::new();
+    if let Some(eco) = &eco_filter {
+        posts_html.push_str(&format!(
+            "<div class=\"banner\">Showing posts classified as {} <a href=\"/\">Show all</a></div>",
+            encode_safe(eco)
         ));
-        posts_html.push_str(&snippet);
+    }
+    let tree = state.db.open_tree("posts")?;
+    // Keys sort by posting time, so walking them backwards lists newest first.
+    for entry in tree.iter().rev() {
+        let (_, value) = entry?;
+        let Some(post) = decode_post(&value) else {
+            continue;
+        };
+        if let Some(eco) = &eco_filter {
+            let matches = post.opening.as_ref().is_some_and(|o| o.eco.starts_with(eco.as_str()));
+            if !matches {
+                continue;
+            }
+        }
+        posts_html.push_str(&render_post(post, &viewer));
     }
 
     let full_html = base_html.replace("<!-- POSTS -->", &posts_html);
-    Html(full_html)
+    Ok(Html(full_html))
+}
+
+async fn search_position(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+    viewer: Viewer,
+) -> AppResult<Html<String>> {
+    let base_html = load_page(&state.config).await?;
+
+    let fen = query.get("fen").map(|fen| fen.trim()).unwrap_or("");
+    let pos = match validate::fen(fen) {
+        Ok(pos) => pos,
+        Err(e) => {
+            let banner = format!(
+                "<div class=\"banner\">Not a valid position: {} <a href=\"/\">Return</a></div>",
+                encode_safe(&e)
+            );
+            return Ok(Html(base_html.replace("<!-- POSTS -->", &banner)));
+        }
+    };
+
+    let posts_tree = state.db.open_tree("posts")?;
+    let index = state.db.open_tree("positions")?;
+    let mut keys = positions::find(&index, positions::hash(&pos))?;
+    keys.sort();
+
+    let mut posts_html = format!(
+        "<div class=\"banner\">{} post(s) reached this position <a href=\"/\">Return</a></div><div class=\"diagram\">{}</div>",
+        keys.len(),
+        fen_to_html(pos.board())
+    );
+    for key in keys.iter().rev() {
+        if let Some(post) = posts_tree.get(key)?.as_deref().and_then(decode_post) {
+            posts_html.push_str(&render_post(post, &viewer));
+        }
+    }
+
+    Ok(Html(base_html.replace("<!-- POSTS -->", &posts_html)))
 }
 
 async fn handle_post(
     State(state): State<Arc<AppState>>,
     mut multipart: Multipart,
-) -> Result<Redirect, Html<String>> {
-    let mut name = "Anonymous".to_string();
+) -> AppResult<Redirect> {
+    let started = Instant::now();
+    let config = &state.config;
+    let mut name = config.board.anonymous_name.clone();
     let mut subject = String::new();
     let mut message = String::new();
     let mut fen = String::new();
-    let mut file_url: Option<String> = None;
+    let mut pgn = String::new();
+    let mut upload: Option<(files::Spooled, String)> = None;
+    let mut spoiler = config.board.require_spoiler;
 
-    while let Some(field) = multipart.next_field().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))? {
+    while let Some(mut field) = multipart.next_field().await? {
         let field_name = field.name().unwrap_or("").to_string();
         if field_name == "name" {
-            name = field.text().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))?.trim().to_string();
+            name = field.text().await?.trim().to_string();
             if name.is_empty() {
-                name = "Anonymous".to_string();
+                name = config.board.anonymous_name.clone();
             }
         } else if field_name == "subject" {
-            subject = field.text().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))?;
+            subject = field.text().await?;
         } else if field_name == "message" {
-            message = field.text().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))?;
+            message = field.text().await?;
         } else if field_name == "fen" {
-            fen = field.text().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))?;
-        } else if field_name == "file" {
-            if let Some(filename) = field.file_name() {
-                let filename = filename.to_string();
-                if !filename.is_empty() {
-                    let content_type = field.content_type().unwrap_or("").to_string();
-                    if content_type.starts_with("image/") {
-                        let data = field.bytes().await.map_err(|e| Html(format!("<h1>Error: {}</h1>", e)))?;
-                        if data.len() > 5 * 1024 * 1024 {
-                            return Err(Html("<h1>File too large (max 5MB)</h1>".to_string()));
-                        }
-                        let ext = Path::new(&filename).extension().and_then(|os| os.to_str()).unwrap_or("");
-                        if ext.is_empty() {
-                            continue;
-                        }
-                        let uuid_str = Uuid::new_v4().simple().to_string();
-                        let new_filename = format!("{}.{}", uuid_str, ext);
-                        let uploads_dir = Path::new("static/uploads");
-                        if !uploads_dir.exists() {
-                            create_dir_all(uploads_dir).await.map_err(|e| Html(format!("<h1>Error creating dir: {}</h1>", e)))?;
-                        }
-                        let path = uploads_dir.join(&new_filename);
-                        write(&path, &data).await.map_err(|e| Html(format!("<h1>Error writing file: {}</h1>", e)))?;
-                        file_url = Some(format!("/static/uploads/{}", new_filename));
-                    }
+            fen = field.text().await?;
+        } else if field_name == "pgn" && config.features.pgn {
+            pgn = field.text().await?;
+        } else if field_name == "spoiler" {
+            spoiler = true;
+        } else if field_name == "file" && let Some(filename) = field.file_name() {
+            let filename = filename.to_string();
+            if !filename.is_empty() {
+                let Some(spooled) = files::spool(&mut field, config.limits.max_file_size).await? else {
+                    return Err(AppError::TooLarge(format!(
+                        "File too large (max {} KB)",
+                        config.limits.max_file_size / 1024
+                    )));
+                };
+                // The bytes decide the format; the extension only has to agree.
+                let Some(format) = Format::detect(&spooled.head) else {
+                    return Err(AppError::Validation(
+                        "Only JPEG, PNG, GIF and WebP images and WebM, MP4, MP3, OGG and FLAC clips can be uploaded"
+                            .to_string(),
+                    ));
+                };
+                let ext = Path::new(&filename).extension().and_then(|os| os.to_str()).unwrap_or("").to_lowercase();
+                if !ext.is_empty() && !format.has_extension(&ext) {
+                    return Err(AppError::Validation(format!("File contents do not match the .{} extension", ext)));
                 }
+                upload = Some((spooled, format.extension().to_string()));
             }
         }
     }
 
     if message.trim().is_empty() {
-        return Err(Html("<h1><a href=\"/\">Message empty - Click here to return</a></h1>".to_string()));
+        return Err(AppError::Validation("Message empty".to_string()));
     }
 
-    let now = Utc::now();
-    let formatted_date = now.format("%Y-%m-%d %H:%M:%S").to_string();
+    let fen = if fen.trim().is_empty() { None } else { Some(fen.trim().to_string()) };
+    if let Some(fen) = &fen {
+        validate::fen(fen).map_err(|e| AppError::Validation(format!("Invalid FEN: {}", e)))?;
+    }
+    let pgn = if pgn.trim().is_empty() { None } else { Some(pgn.trim().to_string()) };
+    // A game the parser cannot follow is still posted, just not classified.
+    let opening = match pgn.as_deref().and_then(|pgn| pgn::positions(pgn).ok()) {
+        Some(positions) => eco::classify_game(&positions),
+        None => fen.as_deref().and_then(parse_fen).as_ref().and_then(eco::classify_position),
+    }
+    .filter(|_| config.features.eco);
 
-    let post = Post {
+    // The file is stored only once the post is known to be valid, so a
+    // rejected post leaves nothing behind.
+    let file_size = upload.as_ref().map_or(0, |(spooled, _)| spooled.size);
+    if config.board.reject_duplicate_files
+        && let Some((spooled, _)) = &upload
+        && files::is_posted(&state.db, &spooled.sha256)?
+    {
+        return Err(AppError::Validation("This file was already posted".to_string()));
+    }
+    let (file_sha256, file_url) = match &upload {
+        Some((spooled, ext)) => {
+            let file_url =
+                files::acquire(&state.db, state.storage.as_ref(), spooled.path(), &spooled.sha256, ext).await?;
+            (Some(spooled.sha256), Some(file_url))
+        }
+        None => (None, None),
+    };
+
+    let mut post = Post {
         name,
         subject,
         message,
-        date: formatted_date.clone(),
-        fen: if fen.trim().is_empty() { None } else { Some(fen) },
+        created_at: Utc::now(),
+        fen,
         file_url,
+        pgn,
+        opening,
+        spoiler: spoiler && file_sha256.is_some(),
     };
 
-    let tree = state.db.open_tree("posts").unwrap();
-    let key = IVec::from(formatted_date.as_bytes()); // Use date as key for sorting
-    let value = serialize(&post).unwrap();
-    tree.insert(key, value).unwrap();
+    let key = match save_post(&state.db, &mut post) {
+        Ok(key) => key,
+        Err(e) => {
+            // The post never took its reference, so give it back.
+            if let Some(sha256) = &file_sha256 {
+                files::release(&state.db, state.storage.as_ref(), sha256).await?;
+            }
+            return Err(e);
+        }
+    };
+    let index = state.db.open_tree("positions")?;
+    positions::index_post(&index, &key, &post)?;
+    tracing::info!(
+        created_at = %post.created_at,
+        file_size,
+        fen = post.fen.is_some(),
+        pgn = post.pgn.is_some(),
+        latency_ms = started.elapsed().as_millis() as u64,
+        "post created"
+    );
 
     Ok(Redirect::to("/"))
 }
 
+/// Inserts `post` under a fresh key and returns it.
+fn save_post(db: &Db, post: &mut Post) -> AppResult<[u8; 8]> {
+    let tree = db.open_tree("posts")?;
+    // A post in the same microsecond as another would take its key, so the
+    // later one moves up a microsecond instead.
+    loop {
+        let key = post_key(post.created_at);
+        if tree.compare_and_swap(key, None::<&[u8]>, Some(serialize(&*post)?))?.is_ok() {
+            return Ok(key);
+        }
+        post.created_at += TimeDelta::microseconds(1);
+    }
+}
+
 struct AppState {
     db: Db,
+    config: Config,
+    storage: Arc<dyn Storage>,
+}
+
+#[derive(Parser)]
+#[command(about = "Chess imageboard backed by sled")]
+struct Cli {
+    /// TOML configuration file [default: config.toml, if present]
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
 }
 
 #[tokio::main]
 async fn main() {
-    let db = sled::open("chess_ib_db").unwrap();
+    let cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("Configuration error: {}", e);
+        std::process::exit(1);
+    });
+    logging::init(&config.logging);
+    let db = sled::open(&config.paths.database).unwrap();
+
+    let moved = migrate::date_keys(&db).unwrap();
+    if moved > 0 {
+        tracing::info!(moved, "re-keyed posts by timestamp");
+    }
+    let rewritten = migrate::spoiler_flags(&db).unwrap();
+    if rewritten > 0 {
+        tracing::info!(rewritten, "added spoiler flags to existing posts");
+    }
+    let storage = storage::open(&config).unwrap_or_else(|e| {
+        eprintln!("Storage error: {}", e);
+        std::process::exit(1);
+    });
+    let adopted = migrate::file_hashes(&db, storage.as_ref()).await.unwrap();
+    if adopted > 0 {
+        tracing::info!(files = adopted, "recorded existing uploads by content hash");
+    }
+
+    // Databases created before the position index existed get it built once.
+    let index = db.open_tree("positions").unwrap();
+    if index.is_empty() {
+        for (key, value) in db.open_tree("posts").unwrap().iter().map(|res| res.unwrap()) {
+            if let Some(post) = decode_post(&value) {
+                positions::index_post(&index, &key, &post).unwrap();
+            }
+        }
+    }
 
-    let state = Arc::new(AppState { db });
+    let bind = config.server.bind;
+    let app = app(Arc::new(AppState { db: db.clone(), config, storage }));
 
-    let app = Router::new()
+    let listener = tokio::net::TcpListener::bind(bind).await.unwrap();
+    tracing::info!(%bind, "listening");
+    axum::serve(listener, app).with_graceful_shutdown(shutdown_signal()).await.unwrap();
+    // sled flushes on its own timer; make sure the last posts reach disk.
+    match db.flush_async().await {
+        Ok(bytes) => tracing::info!(bytes, "database flushed, exiting"),
+        Err(e) => tracing::error!(error = %e, "flushing the database failed"),
+    }
+}
+
+/// Every route, with its layers, over `state`.
+fn app(state: Arc<AppState>) -> Router {
+    let config = &state.config;
+    // Only posting takes a body. Leave room for the text fields next to the
+    // largest allowed file; a larger request is cut off where it crosses
+    // the limit rather than read to the end.
+    let post_limit = config.limits.max_file_size + 1024 * 1024;
+    let mut app = Router::new()
         .route("/", get(serve_index))
-        .route("/post", post(handle_post))
-        .nest_service("/static", ServeDir::new("static"))
-        .with_state(state);
+        .route("/post", post(handle_post).layer(DefaultBodyLimit::max(post_limit)))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .route("/static/uploads/{*name}", get(serve_upload));
+    // The file server below would hand out whatever sits in an uploads
+    // directory inside it, shards included, as its extension suggests and
+    // without `nosniff`. Send those paths through `serve_upload` instead,
+    // which only serves flat names.
+    if let Some(subpath) = static_subpath(&config.paths.static_dir, &config.paths.uploads).filter(|sub| sub != "uploads") {
+        app = app.route(&format!("/static/{}/{{*name}}", subpath), get(serve_upload));
+    }
+    if config.features.position_search {
+        app = app.route("/search/position", get(search_position));
+    }
+    app.fallback(error::not_found)
+        .nest_service("/static", ServeDir::new(&config.paths.static_dir))
+        .layer(axum::middleware::from_fn(error::negotiate))
+        .layer(logging::trace_layer())
+        .with_state(state)
+}
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+/// Where `dir` appears under `/static` if it lies inside `static_dir`, as a
+/// URL path such as `uploads` or `media/uploads`.
+fn static_subpath(static_dir: &Path, dir: &Path) -> Option<String> {
+    let canonical = |path: &Path| std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let relative = canonical(dir).strip_prefix(canonical(static_dir)).ok()?.to_path_buf();
+    let parts: Vec<&str> = relative.components().map(|part| part.as_os_str().to_str()).collect::<Option<_>>()?;
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// An upload from whichever backend is configured, with the type its
+/// extension implies; `nosniff` stops browsers from guessing another one,
+/// such as HTML, from the content.
+async fn serve_upload(
+    State(state): State<Arc<AppState>>,
+    UrlPath(name): UrlPath<String>,
+    request: Request,
+) -> AppResult<Response> {
+    let not_found = || AppError::NotFound(format!("No such file: {}", name));
+    if !storage::valid_name(&name) {
+        return Err(not_found());
+    }
+    let mut response = match state.storage.serve(&name, request).await {
+        Ok(response) => response,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(not_found()),
+        Err(e) => return Err(e.into()),
+    };
+    response.headers_mut().insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    Ok(response)
+}
+
+/// Resolves on Ctrl-C or SIGTERM. The server then stops accepting
+/// connections and lets in-flight requests finish before `serve` returns.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("failed to listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("shutdown requested, draining in-flight requests");
 }
\ No newline at end of file
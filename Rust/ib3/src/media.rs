@@ -0,0 +1,67 @@
+/// `ftyp` brands of MP4 files browsers play. QuickTime and HEIF share the
+/// box layout but are not accepted.
+const MP4_BRANDS: [&[u8; 4]; 11] =
+    [b"isom", b"iso2", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"dash", b"M4V ", b"M4A "];
+
+/// Video and audio containers accepted next to images.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Container {
+    WebM,
+    Mp4,
+    Mp3,
+    Ogg,
+    Flac,
+}
+
+impl Container {
+    /// Container of an upload going by its leading bytes.
+    pub fn sniff(bytes: &[u8]) -> Option<Container> {
+        let head = &bytes[..bytes.len().min(64)];
+        if head.starts_with(b"\x1A\x45\xDF\xA3") {
+            // Matroska too starts with an EBML header; only the "webm"
+            // doctype is one browsers play.
+            return head.windows(4).any(|window| window == b"webm").then_some(Container::WebM);
+        }
+        if head.get(4..8) == Some(b"ftyp".as_slice()) {
+            let brand = head.get(8..12)?;
+            return MP4_BRANDS.iter().any(|known| known.as_slice() == brand).then_some(Container::Mp4);
+        }
+        if head.starts_with(b"OggS") {
+            return Some(Container::Ogg);
+        }
+        if head.starts_with(b"fLaC") {
+            return Some(Container::Flac);
+        }
+        // An ID3 tag, or straight away an MPEG frame header for layer III.
+        let mpeg_frame = head.len() >= 2 && head[0] == 0xFF && head[1] & 0xE6 == 0xE2;
+        if head.starts_with(b"ID3") || mpeg_frame {
+            return Some(Container::Mp3);
+        }
+        None
+    }
+
+    pub fn from_extension(ext: &str) -> Option<Container> {
+        match ext {
+            "webm" => Some(Container::WebM),
+            "mp4" => Some(Container::Mp4),
+            "mp3" => Some(Container::Mp3),
+            "ogg" => Some(Container::Ogg),
+            "flac" => Some(Container::Flac),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Container::WebM => "webm",
+            Container::Mp4 => "mp4",
+            Container::Mp3 => "mp3",
+            Container::Ogg => "ogg",
+            Container::Flac => "flac",
+        }
+    }
+
+    pub fn is_video(self) -> bool {
+        matches!(self, Container::WebM | Container::Mp4)
+    }
+}
@@ -0,0 +1,200 @@
+use crate::eco::{self, Opening};
+use crate::error::AppResult;
+use crate::files::{self, FileEntry};
+use crate::storage::Storage;
+use crate::{decode_post, parse_fen, post_key, Post};
+use bincode::{deserialize, serialize};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+use sled::Db;
+
+/// Record layout written before PGN support; bincode is not self-describing,
+/// so old entries have to be decoded with the old shape.
+#[derive(Deserialize)]
+struct LegacyPost {
+    name: String,
+    subject: String,
+    message: String,
+    date: String,
+    fen: Option<String>,
+    file_url: Option<String>,
+}
+
+/// Record layout from when posts were keyed by their date string.
+#[derive(Deserialize)]
+struct DatedPost {
+    name: String,
+    subject: String,
+    message: String,
+    date: String,
+    fen: Option<String>,
+    file_url: Option<String>,
+    pgn: Option<String>,
+    opening: Option<Opening>,
+}
+
+/// Record layout from before spoilers, under the current timestamp keys.
+#[derive(Deserialize)]
+struct KeyedPost {
+    name: String,
+    subject: String,
+    message: String,
+    created_at: DateTime<Utc>,
+    fen: Option<String>,
+    file_url: Option<String>,
+    pgn: Option<String>,
+    opening: Option<Opening>,
+}
+
+/// Posts used to be keyed by a "%Y-%m-%d %H:%M:%S" string, so two posts in
+/// the same second overwrote each other. Moves every such post to its
+/// `post_key` with the date stored as a timestamp, and returns how many
+/// moved. Safe to rerun after an interruption.
+pub fn date_keys(db: &Db) -> AppResult<usize> {
+    let posts = db.open_tree("posts")?;
+    let old_keys: Vec<_> = posts
+        .iter()
+        .keys()
+        .filter(|key| key.as_ref().map_or(true, |key| key.len() != 8))
+        .collect::<sled::Result<_>>()?;
+    let mut moved = 0;
+    for key in old_keys {
+        let Some(value) = posts.get(&key)? else {
+            continue;
+        };
+        let Some(post) = upgrade(&value) else {
+            tracing::warn!(key = %String::from_utf8_lossy(&key), "skipping a post that could not be decoded");
+            continue;
+        };
+        posts.insert(post_key(post.created_at), serialize(&post)?)?;
+        posts.remove(&key)?;
+        moved += 1;
+    }
+    if moved > 0 {
+        // Index entries still point at the old keys; an empty index is
+        // rebuilt from the posts on startup.
+        db.open_tree("positions")?.clear()?;
+    }
+    Ok(moved)
+}
+
+fn upgrade(bytes: &[u8]) -> Option<Post> {
+    if let Ok(old) = deserialize::<DatedPost>(bytes) {
+        return Some(Post {
+            name: old.name,
+            subject: old.subject,
+            message: old.message,
+            created_at: parse_date(&old.date)?,
+            fen: old.fen,
+            file_url: old.file_url,
+            pgn: old.pgn,
+            opening: old.opening,
+            spoiler: false,
+        });
+    }
+    let old: LegacyPost = deserialize(bytes).ok()?;
+    let opening = old.fen.as_deref().and_then(parse_fen).as_ref().and_then(eco::classify_position);
+    Some(Post {
+        name: old.name,
+        subject: old.subject,
+        message: old.message,
+        created_at: parse_date(&old.date)?,
+        fen: old.fen,
+        file_url: old.file_url,
+        pgn: None,
+        opening,
+        spoiler: false,
+    })
+}
+
+/// Rewrites posts stored before the spoiler flag in the current layout,
+/// unspoilered, and returns how many were rewritten. Posts that already
+/// decode are left alone, so reruns are cheap.
+pub fn spoiler_flags(db: &Db) -> AppResult<usize> {
+    let posts = db.open_tree("posts")?;
+    let mut rewritten = 0;
+    for entry in posts.iter() {
+        let (key, value) = entry?;
+        // The old layout is a prefix of the new one, so only the new shape
+        // can tell them apart: it fails on the missing flag.
+        if decode_post(&value).is_some() {
+            continue;
+        }
+        let Ok(old) = deserialize::<KeyedPost>(&value) else {
+            continue;
+        };
+        let post = Post {
+            name: old.name,
+            subject: old.subject,
+            message: old.message,
+            created_at: old.created_at,
+            fen: old.fen,
+            file_url: old.file_url,
+            pgn: old.pgn,
+            opening: old.opening,
+            spoiler: false,
+        };
+        posts.insert(&key, serialize(&post)?)?;
+        rewritten += 1;
+    }
+    Ok(rewritten)
+}
+
+/// Dates were written from `Utc::now()`, so the naive time is UTC.
+fn parse_date(date: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").ok().map(|date| date.and_utc())
+}
+
+/// Uploads from before deduplication have no entry in the `files` tree.
+/// Hashes each one and records it with its reference count; posts whose
+/// content was uploaded twice are pointed at the first copy and the other
+/// is deleted. Returns how many files were recorded. Runs only while the
+/// tree is empty, since every upload after it goes through `files::acquire`.
+pub async fn file_hashes(db: &Db, storage: &dyn Storage) -> AppResult<usize> {
+    let files_tree = db.open_tree("files")?;
+    if !files_tree.is_empty() {
+        return Ok(0);
+    }
+    let posts = db.open_tree("posts")?;
+    let mut recorded = 0;
+    for entry in posts.iter() {
+        let (key, value) = entry?;
+        let Some(mut post) = decode_post(&value) else {
+            continue;
+        };
+        let Some(file_url) = post.file_url.clone() else {
+            continue;
+        };
+        let Some(filename) = file_url.strip_prefix("/static/uploads/") else {
+            continue;
+        };
+        let bytes = match storage.get(filename).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                tracing::warn!(file_url = %file_url, error = %e, "upload missing, not deduplicated");
+                continue;
+            }
+        };
+        let sha256 = files::sha256(&bytes);
+        let entry = match files_tree.get(sha256)? {
+            Some(existing) => {
+                let mut entry: FileEntry = deserialize(&existing)?;
+                entry.refs += 1;
+                entry
+            }
+            None => {
+                recorded += 1;
+                FileEntry { file_url: file_url.clone(), refs: 1 }
+            }
+        };
+        files_tree.insert(sha256, serialize(&entry)?)?;
+        if entry.file_url != file_url {
+            post.file_url = Some(entry.file_url);
+            posts.insert(&key, serialize(&post)?)?;
+            if let Err(e) = storage.delete(filename).await {
+                tracing::warn!(file_url = %file_url, error = %e, "could not remove duplicate upload");
+            }
+        }
+    }
+    Ok(recorded)
+}
@@ -0,0 +1,43 @@
+use crate::{parse_fen, pgn, Post};
+use shakmaty::{zobrist::Zobrist64, Chess, EnPassantMode, Position};
+use sled::{IVec, Tree};
+
+/// Zobrist hash of a position, ignoring move counters and any en passant
+/// square that cannot actually be captured, so the same position reached
+/// by different move orders hashes identically.
+pub fn hash(pos: &Chess) -> u64 {
+    pos.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0
+}
+
+/// Every distinct position carried by a post: its FEN diagram plus each
+/// position reached in its PGN.
+pub fn post_hashes(post: &Post) -> Vec<u64> {
+    let mut hashes = Vec::new();
+    if let Some(pos) = post.fen.as_deref().and_then(parse_fen) {
+        hashes.push(hash(&pos));
+    }
+    if let Some(positions) = post.pgn.as_deref().and_then(|pgn| pgn::positions(pgn).ok()) {
+        hashes.extend(positions.iter().map(hash));
+    }
+    hashes.sort_unstable();
+    hashes.dedup();
+    hashes
+}
+
+/// Index entries are `hash (8 bytes, big endian) ++ post key` with an empty
+/// value, so all posts for a position are one prefix scan away.
+pub fn index_post(tree: &Tree, post_key: &[u8], post: &Post) -> sled::Result<()> {
+    for h in post_hashes(post) {
+        let mut key = h.to_be_bytes().to_vec();
+        key.extend_from_slice(post_key);
+        tree.insert(key, &[])?;
+    }
+    Ok(())
+}
+
+pub fn find(tree: &Tree, h: u64) -> sled::Result<Vec<IVec>> {
+    tree.scan_prefix(h.to_be_bytes())
+        .keys()
+        .map(|key| key.map(|key| IVec::from(&key[8..])))
+        .collect()
+}
@@ -0,0 +1,132 @@
+use super::*;
+use axum::body::Body;
+use axum::http::StatusCode;
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+const BOUNDARY: &str = "ib-test-boundary";
+
+/// A board on a temporary database and upload directory.
+struct TestBoard {
+    app: Router,
+    db: Db,
+    uploads: PathBuf,
+    /// Removed on drop.
+    _dir: TempDir,
+}
+
+fn board() -> TestBoard {
+    board_with_uploads("static/uploads")
+}
+
+/// A board keeping uploads in `uploads`, relative to the temporary
+/// directory, which also holds `static`.
+fn board_with_uploads(uploads: &str) -> TestBoard {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::default();
+    config.paths.static_dir = dir.path().join("static");
+    config.paths.uploads = dir.path().join(uploads);
+    std::fs::create_dir_all(&config.paths.static_dir).unwrap();
+    std::fs::create_dir_all(&config.paths.uploads).unwrap();
+    let uploads = config.paths.uploads.clone();
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let storage = storage::open(&config).unwrap();
+    let app = app(Arc::new(AppState { db: db.clone(), config, storage }));
+    TestBoard { app, db, uploads, _dir: dir }
+}
+
+/// Puts `bytes` where local storage keeps `name`.
+fn store(board: &TestBoard, name: &str, bytes: &[u8]) {
+    let dir = board.uploads.join(&name[..2]).join(&name[2..4]);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(name), bytes).unwrap();
+}
+
+async fn get(board: &TestBoard, uri: &str) -> Response {
+    board.app.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap()
+}
+
+/// A `/post` request with `fields` and, if given, one file.
+fn post_form(fields: &[(&str, &str)], file: Option<(&str, &[u8])>) -> Request {
+    let mut body = Vec::new();
+    for (name, value) in fields {
+        body.extend_from_slice(
+            format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", BOUNDARY, name, value).as_bytes(),
+        );
+    }
+    if let Some((filename, bytes)) = file {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+                BOUNDARY, filename
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(bytes);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+    Request::post("/post")
+        .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY))
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn posts(db: &Db) -> Vec<Post> {
+    db.open_tree("posts").unwrap().iter().values().map(|value| decode_post(&value.unwrap()).unwrap()).collect()
+}
+
+#[tokio::test]
+async fn annotated_pgn_is_posted_and_classified() {
+    let board = board();
+    let pgn = "[Event \"Casual\"]\n\n1. e4! c5?! 2. Nf3!? {main line} d6?? *";
+    let response = board.app.oneshot(post_form(&[("message", "Annotated"), ("pgn", pgn)], None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let posts = posts(&board.db);
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].pgn.as_deref(), Some(pgn));
+    assert_eq!(posts[0].opening.as_ref().map(|opening| opening.eco.as_str()), Some("B50"));
+}
+
+#[tokio::test]
+async fn unreadable_pgn_is_posted_unclassified() {
+    let board = board();
+    let response =
+        board.app.oneshot(post_form(&[("message", "Odd game"), ("pgn", "1. e4 e5 2. Ke3")], None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let posts = posts(&board.db);
+    assert_eq!(posts.len(), 1);
+    assert!(posts[0].opening.is_none());
+}
+
+#[tokio::test]
+async fn uploads_are_served_by_name_with_nosniff() {
+    let board = board();
+    store(&board, "abcdef.png", b"\x89PNG");
+    let response = get(&board, "/static/uploads/abcdef.png").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+    assert_eq!(response.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+    assert_eq!(get(&board, "/static/uploads/ab/cd/abcdef.png").await.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn legacy_html_uploads_are_not_served_as_html() {
+    let board = board();
+    store(&board, "abcdxss.html", b"<script>alert(1)</script>");
+    let response = get(&board, "/static/uploads/abcdxss.html").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/octet-stream");
+    assert_eq!(response.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+    assert_eq!(get(&board, "/static/uploads/ab/cd/abcdxss.html").await.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn uploads_elsewhere_in_static_are_not_served_from_shards() {
+    let board = board_with_uploads("static/media/files");
+    store(&board, "abcdxss.html", b"<script>alert(1)</script>");
+    assert_eq!(get(&board, "/static/media/files/ab/cd/abcdxss.html").await.status(), StatusCode::NOT_FOUND);
+    let response = get(&board, "/static/media/files/abcdxss.html").await;
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/octet-stream");
+    assert_eq!(response.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+}
@@ -0,0 +1,90 @@
+use shakmaty::{fen::{Fen, ParseFenError}, Board, CastlingMode, Chess, Color, PositionErrorKinds, Rank, Role};
+
+/// Parses and validates a posted FEN, explaining exactly what is wrong with
+/// it instead of just rejecting it.
+pub fn fen(fen: &str) -> Result<Chess, String> {
+    let fen = fen.trim();
+    check_board_layout(fen.split_whitespace().next().unwrap_or(""))?;
+
+    let parsed = Fen::from_ascii(fen.as_bytes()).map_err(|e| match e {
+        ParseFenError::InvalidTurn => "side to move must be \"w\" or \"b\"".to_string(),
+        ParseFenError::InvalidCastling => "castling rights must be \"-\" or a combination of KQkq".to_string(),
+        ParseFenError::InvalidEpSquare => "en passant square must be \"-\" or a square on rank 3 or 6".to_string(),
+        ParseFenError::InvalidHalfmoveClock => "halfmove clock must be a non-negative number".to_string(),
+        ParseFenError::InvalidFullmoves => "fullmove number must be a positive number".to_string(),
+        other => other.to_string(),
+    })?;
+    let board = parsed.as_setup().board.clone();
+
+    parsed
+        .into_position::<Chess>(CastlingMode::Standard)
+        .map_err(|e| describe(e.kinds(), &board))
+}
+
+/// Catches the common hand-typing mistakes that shakmaty only reports as a
+/// generic "invalid board".
+fn check_board_layout(board: &str) -> Result<(), String> {
+    if board.is_empty() {
+        return Err("FEN is empty".to_string());
+    }
+    let ranks: Vec<&str> = board.split('/').collect();
+    if ranks.len() != 8 {
+        return Err(format!("board has {} ranks, expected 8", ranks.len()));
+    }
+    for (i, rank) in ranks.iter().enumerate() {
+        let rank_no = 8 - i;
+        let mut files = 0;
+        for c in rank.chars() {
+            match c {
+                '1'..='8' => files += c as usize - '0' as usize,
+                'p' | 'n' | 'b' | 'r' | 'q' | 'k' | 'P' | 'N' | 'B' | 'R' | 'Q' | 'K' => files += 1,
+                _ => return Err(format!("rank {} contains unexpected character \"{}\"", rank_no, c)),
+            }
+        }
+        if files != 8 {
+            return Err(format!("rank {} describes {} squares, expected 8", rank_no, files));
+        }
+    }
+    Ok(())
+}
+
+fn describe(kinds: PositionErrorKinds, board: &Board) -> String {
+    let kings = |color: Color| (board.kings() & board.by_color(color)).count();
+    let mut problems = Vec::new();
+    if kinds.contains(PositionErrorKinds::EMPTY_BOARD) {
+        problems.push("the board is empty".to_string());
+    }
+    if kinds.contains(PositionErrorKinds::MISSING_KING) || kinds.contains(PositionErrorKinds::TOO_MANY_KINGS) {
+        for color in [Color::White, Color::Black] {
+            match kings(color) {
+                0 => problems.push(format!("{} has no king", color)),
+                1 => {}
+                n => problems.push(format!("{} has {} kings", color, n)),
+            }
+        }
+    }
+    if kinds.contains(PositionErrorKinds::PAWNS_ON_BACKRANK) {
+        let backranks = board.by_role(Role::Pawn).into_iter().filter(|sq| matches!(sq.rank(), Rank::First | Rank::Eighth));
+        let squares: Vec<String> = backranks.map(|sq| sq.to_string()).collect();
+        problems.push(format!("pawn on the back rank ({})", squares.join(", ")));
+    }
+    if kinds.contains(PositionErrorKinds::INVALID_CASTLING_RIGHTS) {
+        problems.push("castling rights do not match the king and rook placement".to_string());
+    }
+    if kinds.contains(PositionErrorKinds::INVALID_EP_SQUARE) {
+        problems.push("en passant square does not follow a double pawn push".to_string());
+    }
+    if kinds.contains(PositionErrorKinds::OPPOSITE_CHECK) {
+        problems.push("the side not to move is in check".to_string());
+    }
+    if kinds.contains(PositionErrorKinds::IMPOSSIBLE_CHECK) {
+        problems.push("the side to move is in a check that no legal move could have given".to_string());
+    }
+    if kinds.contains(PositionErrorKinds::TOO_MUCH_MATERIAL) {
+        problems.push("too much material for a legal game".to_string());
+    }
+    if problems.is_empty() {
+        problems.push("illegal position".to_string());
+    }
+    problems.join("; ")
+}
@@ -0,0 +1,217 @@
+// Board editor for the FEN field of the post form. Pick a piece from the
+// palette and click squares to place it; the FEN input is kept in sync both
+// ways, so typing a FEN also updates the board.
+(function () {
+    var GLYPHS = {
+        K: '♔', Q: '♕', R: '♖', B: '♗', N: '♘', P: '♙',
+        k: '♚', q: '♛', r: '♜', b: '♝', n: '♞', p: '♟'
+    };
+    var START = 'rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR';
+
+    function emptyBoard() {
+        var board = [];
+        for (var r = 0; r < 8; r++) {
+            board.push([null, null, null, null, null, null, null, null]);
+        }
+        return board;
+    }
+
+    // board[0] is rank 8, board[7] is rank 1; returns null for malformed layouts.
+    function parsePlacement(placement) {
+        var ranks = placement.split('/');
+        if (ranks.length !== 8) {
+            return null;
+        }
+        var board = emptyBoard();
+        for (var r = 0; r < 8; r++) {
+            var f = 0;
+            for (var i = 0; i < ranks[r].length; i++) {
+                var c = ranks[r].charAt(i);
+                if (c >= '1' && c <= '8') {
+                    f += parseInt(c, 10);
+                } else if (GLYPHS[c] && f < 8) {
+                    board[r][f++] = c;
+                } else {
+                    return null;
+                }
+            }
+            if (f !== 8) {
+                return null;
+            }
+        }
+        return board;
+    }
+
+    function placement(board) {
+        var ranks = [];
+        for (var r = 0; r < 8; r++) {
+            var s = '';
+            var empty = 0;
+            for (var f = 0; f < 8; f++) {
+                if (board[r][f]) {
+                    if (empty) {
+                        s += empty;
+                        empty = 0;
+                    }
+                    s += board[r][f];
+                } else {
+                    empty++;
+                }
+            }
+            ranks.push(empty ? s + empty : s);
+        }
+        return ranks.join('/');
+    }
+
+    // Only offer castling rights whose king and rook are still at home.
+    function castling(board, wanted) {
+        var homes = {
+            K: [[7, 4, 'K'], [7, 7, 'R']], Q: [[7, 4, 'K'], [7, 0, 'R']],
+            k: [[0, 4, 'k'], [0, 7, 'r']], q: [[0, 4, 'k'], [0, 0, 'r']]
+        };
+        var rights = '';
+        'KQkq'.split('').forEach(function (right) {
+            var home = homes[right].every(function (h) { return board[h[0]][h[1]] === h[2]; });
+            if (wanted[right] && home) {
+                rights += right;
+            }
+        });
+        return rights || '-';
+    }
+
+    function init(input) {
+        var board = parsePlacement(input.value.split(' ')[0]) || emptyBoard();
+        var selected = 'P';
+
+        var root = document.createElement('div');
+        root.className = 'board-editor';
+        root.hidden = true;
+
+        var toggle = document.createElement('button');
+        toggle.type = 'button';
+        toggle.textContent = 'Board editor';
+        toggle.addEventListener('click', function () {
+            root.hidden = !root.hidden;
+        });
+        input.parentNode.insertBefore(toggle, input.nextSibling);
+        toggle.parentNode.insertBefore(root, toggle.nextSibling);
+
+        var table = document.createElement('table');
+        table.className = 'chess-board';
+        var cells = [];
+        for (var r = 0; r < 8; r++) {
+            var tr = document.createElement('tr');
+            cells.push([]);
+            for (var f = 0; f < 8; f++) {
+                var td = document.createElement('td');
+                td.className = (r + f) % 2 === 0 ? 'light' : 'dark';
+                td.title = 'abcdefgh'.charAt(f) + (8 - r);
+                td.addEventListener('click', place.bind(null, r, f));
+                tr.appendChild(td);
+                cells[r].push(td);
+            }
+            table.appendChild(tr);
+        }
+        root.appendChild(table);
+
+        var palette = document.createElement('div');
+        palette.className = 'palette';
+        'KQRBNPkqrbnpx'.split('').forEach(function (piece) {
+            var span = document.createElement('span');
+            span.textContent = piece === 'x' ? '✕' : GLYPHS[piece];
+            span.title = piece === 'x' ? 'Erase' : piece;
+            span.dataset.piece = piece;
+            span.addEventListener('click', function () {
+                selected = piece;
+                refresh();
+            });
+            palette.appendChild(span);
+        });
+        root.appendChild(palette);
+
+        var controls = document.createElement('div');
+        controls.className = 'controls';
+        var turn = document.createElement('select');
+        [['w', 'White to move'], ['b', 'Black to move']].forEach(function (opt) {
+            var o = document.createElement('option');
+            o.value = opt[0];
+            o.textContent = opt[1];
+            turn.appendChild(o);
+        });
+        turn.addEventListener('change', write);
+        controls.appendChild(turn);
+
+        var rights = {};
+        'KQkq'.split('').forEach(function (right) {
+            var label = document.createElement('label');
+            var box = document.createElement('input');
+            box.type = 'checkbox';
+            box.checked = true;
+            box.addEventListener('change', write);
+            rights[right] = box;
+            label.appendChild(box);
+            label.appendChild(document.createTextNode(right));
+            controls.appendChild(label);
+        });
+
+        [['Start', START], ['Clear', '8/8/8/8/8/8/8/8']].forEach(function (preset) {
+            var button = document.createElement('button');
+            button.type = 'button';
+            button.textContent = preset[0];
+            button.addEventListener('click', function () {
+                board = parsePlacement(preset[1]);
+                turn.value = 'w';
+                write();
+            });
+            controls.appendChild(button);
+        });
+        root.appendChild(controls);
+
+        function place(r, f) {
+            var piece = selected === 'x' ? null : selected;
+            board[r][f] = board[r][f] === piece ? null : piece;
+            write();
+        }
+
+        function write() {
+            var wanted = {};
+            Object.keys(rights).forEach(function (right) {
+                wanted[right] = rights[right].checked;
+            });
+            input.value = placement(board) + ' ' + turn.value + ' ' + castling(board, wanted) + ' - 0 1';
+            refresh();
+        }
+
+        function refresh() {
+            for (var r = 0; r < 8; r++) {
+                for (var f = 0; f < 8; f++) {
+                    cells[r][f].textContent = board[r][f] ? GLYPHS[board[r][f]] : '';
+                }
+            }
+            Array.prototype.forEach.call(palette.children, function (span) {
+                span.className = span.dataset.piece === selected ? 'selected' : '';
+            });
+        }
+
+        input.addEventListener('input', function () {
+            var parts = input.value.trim().split(/\s+/);
+            var parsed = parsePlacement(parts[0]);
+            if (parsed) {
+                board = parsed;
+                if (parts[1] === 'w' || parts[1] === 'b') {
+                    turn.value = parts[1];
+                }
+                refresh();
+            }
+        });
+
+        refresh();
+    }
+
+    document.addEventListener('DOMContentLoaded', function () {
+        var input = document.querySelector('input[name="fen"][data-board-editor]');
+        if (input) {
+            init(input);
+        }
+    });
+})();
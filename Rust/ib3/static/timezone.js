@@ -0,0 +1,14 @@
+// Stores the browser's time zone in the "tz" cookie so the server can show
+// post times in it. Takes effect from the next page load.
+(function () {
+    var zone = Intl.DateTimeFormat().resolvedOptions().timeZone;
+    if (!zone) {
+        return;
+    }
+    var current = document.cookie.split("; ").filter(function (pair) {
+        return pair.indexOf("tz=") === 0;
+    })[0];
+    if (current !== "tz=" + zone) {
+        document.cookie = "tz=" + zone + "; path=/; max-age=31536000; samesite=lax";
+    }
+})();
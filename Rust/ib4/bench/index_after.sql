@@ -0,0 +1,49 @@
+-- Board page as rendered by `load_thread_previews`: one query returning
+-- each OP with its counts and only the last three replies.
+--
+--   pgbench -n -f bench/index_after.sql -D pages=33 -T 30 "$DATABASE_URL"
+\set page random(1, :pages)
+\set offset (:page - 1) * 15
+SELECT id AS board_id FROM boards WHERE slug = 'bench' \gset
+SELECT COUNT(*) FROM threads WHERE board_id = :board_id;
+WITH ops AS (
+    SELECT post_no AS thread_id, bumped_at
+    FROM threads
+    WHERE board_id = :board_id
+    ORDER BY bumped_at DESC, post_no DESC
+    LIMIT 15 OFFSET :offset
+),
+counts AS (
+    SELECT thread_id, COUNT(*) - 1 AS reply_count
+    FROM posts
+    WHERE board_id = :board_id AND thread_id IN (SELECT thread_id FROM ops)
+    GROUP BY thread_id
+),
+images AS (
+    SELECT p.thread_id, COUNT(*) AS image_count
+    FROM posts p
+    JOIN post_files pf ON pf.post_id = p.id
+    WHERE p.board_id = :board_id AND p.thread_id IN (SELECT thread_id FROM ops)
+    GROUP BY p.thread_id
+),
+shown AS (
+    SELECT thread_id AS post_no FROM ops
+    UNION ALL
+    SELECT last.post_no
+    FROM ops o
+    CROSS JOIN LATERAL (
+        SELECT post_no FROM posts
+        WHERE board_id = :board_id AND thread_id = o.thread_id AND post_no <> o.thread_id
+        ORDER BY post_no DESC
+        LIMIT 3
+    ) last
+)
+SELECT p.id, p.board_id, p.post_no, p.thread_id, p.name, p.subject, p.message,
+       COALESCE((SELECT json_agg(pf ORDER BY pf.position) FROM post_files pf WHERE pf.post_id = p.id), '[]') AS files,
+       p.created_at, p.fen, p.pgn, c.reply_count, COALESCE(i.image_count, 0) AS image_count
+FROM shown s
+JOIN posts p ON p.board_id = :board_id AND p.post_no = s.post_no
+JOIN ops o ON o.thread_id = p.thread_id
+JOIN counts c ON c.thread_id = p.thread_id
+LEFT JOIN images i ON i.thread_id = p.thread_id
+ORDER BY o.bumped_at DESC, o.thread_id DESC, p.post_no;
@@ -0,0 +1,40 @@
+-- Board page as rendered before `load_thread_previews`: the OPs, then every
+-- post of each of the 15 threads. pgbench can't loop over a result, so
+-- the thread ids are fetched once more into t1..t15 first.
+--
+--   pgbench -n -f bench/index_before.sql -D pages=33 -T 30 "$DATABASE_URL"
+\set page random(1, :pages)
+\set offset (:page - 1) * 15
+SELECT id AS board_id FROM boards WHERE slug = 'bench' \gset
+SELECT COUNT(*) FROM threads WHERE board_id = :board_id;
+SELECT p.id, p.board_id, p.post_no, p.thread_id, p.name, p.subject, p.message, p.filename, p.thumbname, p.created_at, p.fen, p.pgn
+FROM threads t
+JOIN posts p ON p.board_id = t.board_id AND p.post_no = t.post_no
+WHERE t.board_id = :board_id
+ORDER BY t.bumped_at DESC
+LIMIT 15 OFFSET :offset;
+SELECT t[1] AS t1, t[2] AS t2, t[3] AS t3, t[4] AS t4, t[5] AS t5, t[6] AS t6, t[7] AS t7, t[8] AS t8, t[9] AS t9, t[10] AS t10, t[11] AS t11, t[12] AS t12, t[13] AS t13, t[14] AS t14, t[15] AS t15
+FROM (
+    SELECT array_agg(post_no ORDER BY bumped_at DESC) AS t
+    FROM (
+        SELECT post_no, bumped_at FROM threads
+        WHERE board_id = :board_id
+        ORDER BY bumped_at DESC
+        LIMIT 15 OFFSET :offset
+    ) ops
+) ids \gset
+SELECT id, board_id, post_no, thread_id, name, subject, message, filename, thumbname, created_at, fen, pgn FROM posts WHERE board_id = :board_id AND thread_id = :t1 ORDER BY post_no;
+SELECT id, board_id, post_no, thread_id, name, subject, message, filename, thumbname, created_at, fen, pgn FROM posts WHERE board_id = :board_id AND thread_id = :t2 ORDER BY post_no;
+SELECT id, board_id, post_no, thread_id, name, subject, message, filename, thumbname, created_at, fen, pgn FROM posts WHERE board_id = :board_id AND thread_id = :t3 ORDER BY post_no;
+SELECT id, board_id, post_no, thread_id, name, subject, message, filename, thumbname, created_at, fen, pgn FROM posts WHERE board_id = :board_id AND thread_id = :t4 ORDER BY post_no;
+SELECT id, board_id, post_no, thread_id, name, subject, message, filename, thumbname, created_at, fen, pgn FROM posts WHERE board_id = :board_id AND thread_id = :t5 ORDER BY post_no;
+SELECT id, board_id, post_no, thread_id, name, subject, message, filename, thumbname, created_at, fen, pgn FROM posts WHERE board_id = :board_id AND thread_id = :t6 ORDER BY post_no;
+SELECT id, board_id, post_no, thread_id, name, subject, message, filename, thumbname, created_at, fen, pgn FROM posts WHERE board_id = :board_id AND thread_id = :t7 ORDER BY post_no;
+SELECT id, board_id, post_no, thread_id, name, subject, message, filename, thumbname, created_at, fen, pgn FROM posts WHERE board_id = :board_id AND thread_id = :t8 ORDER BY post_no;
+SELECT id, board_id, post_no, thread_id, name, subject, message, filename, thumbname, created_at, fen, pgn FROM posts WHERE board_id = :board_id AND thread_id = :t9 ORDER BY post_no;
+SELECT id, board_id, post_no, thread_id, name, subject, message, filename, thumbname, created_at, fen, pgn FROM posts WHERE board_id = :board_id AND thread_id = :t10 ORDER BY post_no;
+SELECT id, board_id, post_no, thread_id, name, subject, message, filename, thumbname, created_at, fen, pgn FROM posts WHERE board_id = :board_id AND thread_id = :t11 ORDER BY post_no;
+SELECT id, board_id, post_no, thread_id, name, subject, message, filename, thumbname, created_at, fen, pgn FROM posts WHERE board_id = :board_id AND thread_id = :t12 ORDER BY post_no;
+SELECT id, board_id, post_no, thread_id, name, subject, message, filename, thumbname, created_at, fen, pgn FROM posts WHERE board_id = :board_id AND thread_id = :t13 ORDER BY post_no;
+SELECT id, board_id, post_no, thread_id, name, subject, message, filename, thumbname, created_at, fen, pgn FROM posts WHERE board_id = :board_id AND thread_id = :t14 ORDER BY post_no;
+SELECT id, board_id, post_no, thread_id, name, subject, message, filename, thumbname, created_at, fen, pgn FROM posts WHERE board_id = :board_id AND thread_id = :t15 ORDER BY post_no;
@@ -0,0 +1,41 @@
+-- Fills a /bench/ board with :threads threads of :replies replies each,
+-- every tenth reply carrying an image. Run against a migrated scratch
+-- database; an existing /bench/ board is emptied first.
+--
+--   psql -v threads=500 -v replies=300 -f bench/seed.sql "$DATABASE_URL"
+\set ON_ERROR_STOP on
+
+INSERT INTO boards (slug, title, subtitle)
+VALUES ('bench', 'Benchmark', 'Synthetic threads for bench/')
+ON CONFLICT (slug) DO NOTHING;
+
+-- Threads and their OPs reference each other, so they go in together.
+BEGIN;
+DELETE FROM threads WHERE board_id = (SELECT id FROM boards WHERE slug = 'bench');
+
+INSERT INTO threads (board_id, post_no, bumped_at)
+SELECT b.id, t, '2026-01-01'::TIMESTAMPTZ + t * INTERVAL '1 minute'
+FROM boards b, generate_series(1, :threads) t
+WHERE b.slug = 'bench';
+
+INSERT INTO posts (board_id, post_no, thread_id, name, subject, message, created_at)
+SELECT b.id, t, t, 'Anonymous', 'Thread ' || t, repeat('Opening post text. ', 20), '2026-01-01'::TIMESTAMPTZ
+FROM boards b, generate_series(1, :threads) t
+WHERE b.slug = 'bench';
+
+INSERT INTO posts (board_id, post_no, thread_id, name, message, created_at)
+SELECT b.id, :threads + (t - 1) * :replies + r, t, 'Anonymous', repeat('Reply text. ', 30), '2026-01-01'::TIMESTAMPTZ
+FROM boards b, generate_series(1, :threads) t, generate_series(1, :replies) r
+WHERE b.slug = 'bench';
+
+INSERT INTO post_files (post_id, position, filename, thumbname, original_name)
+SELECT p.id, 0, 'bench.png', 'bench_thumb.jpg', 'bench.png'
+FROM posts p
+JOIN boards b ON b.id = p.board_id
+WHERE b.slug = 'bench' AND p.post_no > :threads AND (p.post_no - :threads) % 10 = 0;
+
+UPDATE boards SET next_post_no = :threads * (:replies + 1) + 1 WHERE slug = 'bench';
+COMMIT;
+ANALYZE threads;
+ANALYZE posts;
+ANALYZE post_files;
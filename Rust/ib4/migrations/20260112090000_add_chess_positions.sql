@@ -0,0 +1,11 @@
+-- up
+ALTER TABLE posts ADD COLUMN fen TEXT;
+ALTER TABLE posts ADD COLUMN pgn TEXT;
+
+-- One row per distinct position a post carries (its FEN and every position
+-- reached in its PGN), keyed by Zobrist hash for transposition-aware search.
+CREATE TABLE post_positions (
+    zobrist BIGINT NOT NULL,
+    post_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    PRIMARY KEY (zobrist, post_id)
+);
@@ -0,0 +1,29 @@
+-- up
+-- Slugs share the URL namespace with the fixed routes, so those are reserved.
+CREATE TABLE boards (
+    id SERIAL PRIMARY KEY,
+    slug VARCHAR(16) NOT NULL UNIQUE
+        CHECK (slug ~ '^[a-z0-9]+$' AND slug NOT IN ('search', 'static', 'uploads', 'thumbs')),
+    title VARCHAR(255) NOT NULL,
+    subtitle VARCHAR(255) NOT NULL DEFAULT '',
+    max_file_size BIGINT NOT NULL DEFAULT 2097152,
+    allowed_extensions TEXT[] NOT NULL DEFAULT '{jpg,jpeg,png,gif,webp}',
+    default_name VARCHAR(255) NOT NULL DEFAULT 'Anonymous',
+    -- Post numbers are handed out per board by bumping this counter.
+    next_post_no BIGINT NOT NULL DEFAULT 1
+);
+
+INSERT INTO boards (slug, title, subtitle)
+VALUES ('chess', 'Chess', 'General chess discussion, puzzles, and diagrams');
+
+-- Existing posts move to /chess/ and keep their ids as post numbers, so
+-- thread_id (which held the OP's id) is already the OP's post number.
+ALTER TABLE posts ADD COLUMN board_id INTEGER REFERENCES boards(id);
+ALTER TABLE posts ADD COLUMN post_no BIGINT;
+UPDATE posts SET board_id = (SELECT id FROM boards WHERE slug = 'chess'), post_no = id;
+ALTER TABLE posts ALTER COLUMN board_id SET NOT NULL;
+ALTER TABLE posts ALTER COLUMN post_no SET NOT NULL;
+UPDATE boards SET next_post_no = (SELECT COALESCE(MAX(id), 0) + 1 FROM posts) WHERE slug = 'chess';
+
+CREATE UNIQUE INDEX posts_board_post_no_idx ON posts (board_id, post_no);
+CREATE INDEX posts_board_thread_idx ON posts (board_id, thread_id);
@@ -0,0 +1,10 @@
+-- up
+-- Board pages list OPs by bump order; this lets them be read straight off
+-- an index instead of sorting every OP on the board.
+CREATE INDEX posts_board_bump_idx ON posts (board_id, bump_timestamp DESC, thread_id DESC)
+    WHERE post_no = thread_id;
+-- Ordering by post_no lets the index page fetch a thread's last replies
+-- without reading the rest, and carrying filename lets it count images
+-- from the index alone.
+DROP INDEX posts_board_thread_idx;
+CREATE INDEX posts_board_thread_idx ON posts (board_id, thread_id, post_no) INCLUDE (filename);
@@ -0,0 +1,16 @@
+-- up
+-- Post times were a UTC "%Y-%m-%d %H:%M" string and bump times Unix
+-- seconds. As TIMESTAMPTZ they sort and compare in SQL and convert to
+-- each reader's time zone when shown.
+ALTER TABLE posts ADD COLUMN created_at TIMESTAMPTZ;
+ALTER TABLE posts ADD COLUMN bumped_at TIMESTAMPTZ;
+UPDATE posts SET created_at = time::TIMESTAMP AT TIME ZONE 'UTC';
+-- Only OPs were ever bumped; replies carried 0.
+UPDATE posts SET bumped_at = CASE WHEN post_no = thread_id THEN to_timestamp(bump_timestamp) ELSE created_at END;
+ALTER TABLE posts ALTER COLUMN created_at SET NOT NULL, ALTER COLUMN created_at SET DEFAULT now();
+ALTER TABLE posts ALTER COLUMN bumped_at SET NOT NULL, ALTER COLUMN bumped_at SET DEFAULT now();
+
+-- Dropping bump_timestamp drops posts_board_bump_idx with it.
+ALTER TABLE posts DROP COLUMN time, DROP COLUMN bump_timestamp;
+CREATE INDEX posts_board_bump_idx ON posts (board_id, bumped_at DESC, thread_id DESC)
+    WHERE post_no = thread_id;
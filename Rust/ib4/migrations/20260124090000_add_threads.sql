@@ -0,0 +1,27 @@
+-- up
+-- A thread is named by its board and its OP's post number, as in URLs.
+-- Posts reference their thread, and a thread references its OP; that key
+-- is checked at commit so the two can be inserted in one transaction.
+CREATE TABLE threads (
+    board_id INTEGER NOT NULL REFERENCES boards(id),
+    post_no BIGINT NOT NULL,
+    bumped_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (board_id, post_no)
+);
+
+-- OPs stranded by the old insert-then-update creation still carry their
+-- temporary thread_id, which names no post; they become their own threads.
+UPDATE posts p SET thread_id = p.post_no
+WHERE NOT EXISTS (SELECT 1 FROM posts op WHERE op.board_id = p.board_id AND op.post_no = p.thread_id);
+
+INSERT INTO threads (board_id, post_no, bumped_at)
+SELECT board_id, post_no, bumped_at FROM posts WHERE post_no = thread_id;
+
+ALTER TABLE posts ADD CONSTRAINT posts_thread_fkey
+    FOREIGN KEY (board_id, thread_id) REFERENCES threads (board_id, post_no) ON DELETE CASCADE;
+ALTER TABLE threads ADD CONSTRAINT threads_op_fkey
+    FOREIGN KEY (board_id, post_no) REFERENCES posts (board_id, post_no) DEFERRABLE INITIALLY DEFERRED;
+
+-- Dropping bumped_at drops posts_board_bump_idx with it.
+ALTER TABLE posts DROP COLUMN bumped_at;
+CREATE INDEX threads_board_bump_idx ON threads (board_id, bumped_at DESC, post_no DESC);
@@ -0,0 +1,35 @@
+-- up
+-- Uploads are stored once per distinct content. Posts still carry the
+-- file names for rendering; file_sha256 ties them to the stored copy.
+CREATE TABLE files (
+    sha256 BYTEA PRIMARY KEY,
+    filename VARCHAR(255) NOT NULL,
+    thumbname VARCHAR(255),
+    size BIGINT NOT NULL,
+    -- Posts referencing this file, kept by the trigger below. Files at zero
+    -- are deleted by the sweep once last_used_at is old enough.
+    ref_count INTEGER NOT NULL DEFAULT 0 CHECK (ref_count >= 0),
+    last_used_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Existing uploads are hashed and linked by the server on its next start.
+ALTER TABLE posts ADD COLUMN file_sha256 BYTEA REFERENCES files(sha256);
+CREATE INDEX posts_board_file_idx ON posts (board_id, file_sha256) WHERE file_sha256 IS NOT NULL;
+
+CREATE FUNCTION posts_count_file_refs() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') AND OLD.file_sha256 IS NOT NULL THEN
+        UPDATE files SET ref_count = ref_count - 1, last_used_at = now() WHERE sha256 = OLD.file_sha256;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') AND NEW.file_sha256 IS NOT NULL THEN
+        UPDATE files SET ref_count = ref_count + 1, last_used_at = now() WHERE sha256 = NEW.file_sha256;
+    END IF;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER posts_file_refs
+    AFTER INSERT OR DELETE OR UPDATE OF file_sha256 ON posts
+    FOR EACH ROW EXECUTE FUNCTION posts_count_file_refs();
+
+ALTER TABLE boards ADD COLUMN reject_duplicate_files BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,12 @@
+-- up
+-- Video and audio uploads next to images. Width and height are those of
+-- the picture (NULL for audio); duration is only known for clips ffprobe
+-- could read.
+ALTER TABLE files
+    ADD COLUMN kind TEXT NOT NULL DEFAULT 'image' CHECK (kind IN ('image', 'video', 'audio')),
+    ADD COLUMN width INTEGER,
+    ADD COLUMN height INTEGER,
+    ADD COLUMN duration_ms INTEGER;
+
+-- New boards accept clips too; existing boards keep their list.
+ALTER TABLE boards ALTER COLUMN allowed_extensions SET DEFAULT '{jpg,jpeg,png,gif,webp,webm,mp4,mp3,ogg,flac}';
@@ -0,0 +1,53 @@
+-- up
+-- A post may carry several files. Each row keeps what the post shows of
+-- one of them, in upload order; files still holds the single stored copy
+-- and counts the rows that use it.
+CREATE TABLE post_files (
+    post_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    position SMALLINT NOT NULL CHECK (position >= 0),
+    -- NULL, like size, only for uploads from before deduplication that
+    -- the server has not hashed yet.
+    file_sha256 BYTEA REFERENCES files(sha256),
+    filename VARCHAR(255) NOT NULL,
+    thumbname VARCHAR(255),
+    original_name VARCHAR(255) NOT NULL,
+    size BIGINT,
+    kind TEXT NOT NULL DEFAULT 'image' CHECK (kind IN ('image', 'video', 'audio')),
+    width INTEGER,
+    height INTEGER,
+    duration_ms INTEGER,
+    PRIMARY KEY (post_id, position)
+);
+CREATE INDEX post_files_sha256_idx ON post_files (file_sha256) WHERE file_sha256 IS NOT NULL;
+
+-- Every existing file moves over as its post's first, with reference
+-- counts unchanged: the old trigger goes before the rows are copied and
+-- the new one comes after.
+DROP TRIGGER posts_file_refs ON posts;
+DROP FUNCTION posts_count_file_refs();
+
+INSERT INTO post_files (post_id, position, file_sha256, filename, thumbname, original_name, size, kind, width, height, duration_ms)
+SELECT p.id, 0, p.file_sha256, p.filename, p.thumbname, p.filename, f.size, COALESCE(f.kind, 'image'), f.width, f.height, f.duration_ms
+FROM posts p
+LEFT JOIN files f ON f.sha256 = p.file_sha256
+WHERE p.filename IS NOT NULL;
+
+ALTER TABLE posts DROP COLUMN filename, DROP COLUMN thumbname, DROP COLUMN file_sha256;
+-- Dropping filename took the thread index that included it along.
+CREATE INDEX posts_board_thread_idx ON posts (board_id, thread_id, post_no);
+
+CREATE FUNCTION post_files_count_refs() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') AND OLD.file_sha256 IS NOT NULL THEN
+        UPDATE files SET ref_count = ref_count - 1, last_used_at = now() WHERE sha256 = OLD.file_sha256;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') AND NEW.file_sha256 IS NOT NULL THEN
+        UPDATE files SET ref_count = ref_count + 1, last_used_at = now() WHERE sha256 = NEW.file_sha256;
+    END IF;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER post_files_refs
+    AFTER INSERT OR DELETE OR UPDATE OF file_sha256 ON post_files
+    FOR EACH ROW EXECUTE FUNCTION post_files_count_refs();
@@ -0,0 +1,5 @@
+-- up
+-- A spoilered file is shown as a generic image until clicked. Boards can
+-- require it for every file.
+ALTER TABLE post_files ADD COLUMN spoiler BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE boards ADD COLUMN require_spoiler BOOLEAN NOT NULL DEFAULT false;
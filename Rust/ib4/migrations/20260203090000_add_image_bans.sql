@@ -0,0 +1,13 @@
+-- up
+-- Perceptual hash (dHash) of each stored image, so copies that were resized
+-- or recompressed can be matched. NULL for clips, audio and files stored
+-- before this column.
+ALTER TABLE files ADD COLUMN phash BIGINT;
+
+-- Images moderators have banned. Uploads within the configured Hamming
+-- distance of any of these hashes are turned away.
+CREATE TABLE banned_images (
+    phash BIGINT PRIMARY KEY,
+    reason TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
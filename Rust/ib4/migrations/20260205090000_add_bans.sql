@@ -0,0 +1,11 @@
+-- up
+-- Addresses that may not post, from word filters with the ban action. A
+-- NULL expires_at lasts until the row is deleted.
+CREATE TABLE bans (
+    id SERIAL PRIMARY KEY,
+    ip TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ
+);
+CREATE INDEX bans_ip_idx ON bans (ip);
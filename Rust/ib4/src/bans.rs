@@ -0,0 +1,72 @@
+use crate::config::ServerConfig;
+use crate::error::AppResult;
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::net::{IpAddr, SocketAddr};
+
+/// A ban in force on an address.
+pub struct Ban {
+    pub reason: String,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Ban {
+    /// What the banned poster is told.
+    pub fn notice(&self) -> String {
+        match self.expires_at {
+            Some(until) => format!("You are banned until {}: {}", until.format("%Y-%m-%d %H:%M UTC"), self.reason),
+            None => format!("You are banned: {}", self.reason),
+        }
+    }
+}
+
+/// The poster's address: the connection's, or behind a proxy the one it
+/// appended to `X-Forwarded-For`. Earlier entries came from the client and
+/// prove nothing.
+pub fn client_ip(config: &ServerConfig, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+    if !config.behind_proxy {
+        return peer.ip();
+    }
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .next_back()
+        .and_then(|entry| entry.trim().parse().ok())
+        .unwrap_or(peer.ip())
+}
+
+/// The longest-lasting ban in force on `ip`, if any.
+pub async fn find(pool: &PgPool, ip: IpAddr) -> AppResult<Option<Ban>> {
+    let ban = sqlx::query_as!(
+        Ban,
+        r#"
+        SELECT reason, expires_at FROM bans
+        WHERE ip = $1 AND (expires_at IS NULL OR expires_at > now())
+        ORDER BY expires_at DESC NULLS FIRST
+        LIMIT 1
+        "#,
+        ip.to_string()
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(ban)
+}
+
+/// Bans `ip` for `hours`, or until lifted if 0.
+pub async fn add(pool: &PgPool, ip: IpAddr, reason: &str, hours: u32) -> AppResult<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO bans (ip, reason, expires_at)
+        VALUES ($1, $2, CASE WHEN $3 = 0 THEN NULL ELSE now() + make_interval(hours => $3) END)
+        "#,
+        ip.to_string(),
+        reason,
+        hours as i32
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
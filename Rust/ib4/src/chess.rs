@@ -0,0 +1,64 @@
+use crate::pgn;
+use shakmaty::{fen::Fen, zobrist::Zobrist64, CastlingMode, Chess, Color, EnPassantMode, File, Position, Rank, Role, Square};
+
+fn get_unicode(color: Color, role: Role) -> &'static str {
+    match (color, role) {
+        (Color::White, Role::King) => "♔",
+        (Color::White, Role::Queen) => "♕",
+        (Color::White, Role::Rook) => "♖",
+        (Color::White, Role::Bishop) => "♗",
+        (Color::White, Role::Knight) => "♘",
+        (Color::White, Role::Pawn) => "♙",
+        (Color::Black, Role::King) => "♚",
+        (Color::Black, Role::Queen) => "♛",
+        (Color::Black, Role::Rook) => "♜",
+        (Color::Black, Role::Bishop) => "♝",
+        (Color::Black, Role::Knight) => "♞",
+        (Color::Black, Role::Pawn) => "♟",
+    }
+}
+
+pub fn diagram_html(pos: &Chess) -> String {
+    let board = pos.board();
+    let mut html = "<table class=\"chess-board\"><tbody>".to_string();
+    for r in (0..8).rev() {
+        html.push_str("<tr>");
+        for f in 0..8 {
+            let square = Square::from_coords(File::new(f), Rank::new(r));
+            let piece_str = board.piece_at(square).map_or("&nbsp;", |piece| get_unicode(piece.color, piece.role));
+            let sq_class = if (r + f) % 2 == 0 { "light" } else { "dark" };
+            html.push_str(&format!("<td class=\"{}\">{}</td>", sq_class, piece_str));
+        }
+        html.push_str("</tr>");
+    }
+    html.push_str("</tbody></table>");
+    html
+}
+
+pub fn parse_fen(fen: &str) -> Option<Chess> {
+    Fen::from_ascii(fen.trim().as_bytes())
+        .ok()?
+        .into_position::<Chess>(CastlingMode::Standard)
+        .ok()
+}
+
+/// Zobrist hash of a position, ignoring move counters and any en passant
+/// square that cannot actually be captured, so transpositions hash alike.
+pub fn position_hash(pos: &Chess) -> i64 {
+    pos.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0 as i64
+}
+
+/// Every distinct position carried by a post: its FEN diagram plus each
+/// position reached in its PGN.
+pub fn post_hashes(fen: Option<&str>, pgn: Option<&str>) -> Vec<i64> {
+    let mut hashes = Vec::new();
+    if let Some(pos) = fen.and_then(parse_fen) {
+        hashes.push(position_hash(&pos));
+    }
+    if let Some(positions) = pgn.and_then(|pgn| pgn::positions(pgn).ok()) {
+        hashes.extend(positions.iter().map(position_hash));
+    }
+    hashes.sort_unstable();
+    hashes.dedup();
+    hashes
+}
@@ -0,0 +1,562 @@
+use crate::filters::Filters;
+use crate::media::Container;
+use image::ImageFormat;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Read when no `--config` flag is given and the file exists.
+pub const DEFAULT_PATH: &str = "config.toml";
+
+/// Everything that used to be hard-coded. Every key is optional; see
+/// `config.example.toml` for the defaults and the environment overrides.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub site: SiteConfig,
+    pub limits: LimitsConfig,
+    pub thumbnails: ThumbnailConfig,
+    pub paths: PathsConfig,
+    pub storage: StorageConfig,
+    pub features: FeaturesConfig,
+    pub moderation: ModerationConfig,
+    pub logging: LoggingConfig,
+    /// Boards listed here are created or updated at startup; boards that are
+    /// only in the database are left alone.
+    pub boards: Vec<BoardConfig>,
+    /// Word filters, tried in order on every post's name, subject and comment.
+    pub filters: Vec<FilterConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: SocketAddr,
+    /// Take the poster's address from the last `X-Forwarded-For` entry, as
+    /// appended by a reverse proxy in front. Off, the header is ignored,
+    /// since anyone can send it.
+    pub behind_proxy: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: Option<String>,
+    /// Drops, recreates and seeds the database on every start.
+    pub dev_mode: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteConfig {
+    pub title: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Threads per board page and hits per search page.
+    pub per_page: i64,
+    /// Latest replies shown under each thread on a board page.
+    pub preview_replies: usize,
+    /// Largest image accepted, in pixels, whatever its file size. Animated
+    /// thumbnails count the pixels of every frame against it.
+    pub max_image_pixels: u64,
+    /// Files one post may carry; each is held to the board's size limit.
+    pub max_files_per_post: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThumbnailConfig {
+    pub width: u32,
+    pub height: u32,
+    /// Animated GIF, APNG and WebP uploads get animated GIF thumbnails
+    /// instead of their first frame.
+    pub animated: bool,
+    /// Used to read video and audio uploads. Either may be missing; clips
+    /// then get a placeholder instead of a thumbnail.
+    pub ffmpeg: PathBuf,
+    pub ffprobe: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    pub static_dir: PathBuf,
+    pub uploads: PathBuf,
+    pub thumbs: PathBuf,
+    /// Where uploads are also kept as received, metadata included, for the
+    /// site's operators. Never served over HTTP. With the S3 backend only
+    /// whether it is set matters; originals then go under `originals/` in
+    /// the bucket.
+    pub originals: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    pub s3: S3Config,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// The directories under `paths`.
+    #[default]
+    Local,
+    /// An S3 bucket, on AWS or any compatible server such as MinIO.
+    S3,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+    /// Base URL of an S3-compatible server; AWS when unset.
+    pub endpoint: Option<String>,
+    pub bucket: String,
+    pub region: String,
+    /// Taken from the usual `AWS_*` environment variables when unset.
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeaturesConfig {
+    pub text_search: bool,
+    pub position_search: bool,
+    /// Serve Prometheus metrics at `/metrics`.
+    pub metrics: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModerationConfig {
+    /// Uploads whose perceptual hash differs from a banned one in at most
+    /// this many of its 64 bits are refused; 0 matches only the same hash.
+    pub image_ban_distance: u32,
+    /// Shown to whoever posts a banned image.
+    pub banned_image_message: String,
+    /// Most links (`http://`, `https://` or `www.`) one post may carry; 0
+    /// allows none.
+    pub max_links_per_post: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterConfig {
+    /// A word or phrase, matched as a whole and ignoring case, or a regular
+    /// expression if `regex` is set.
+    pub pattern: String,
+    #[serde(default)]
+    pub regex: bool,
+    pub action: FilterAction,
+    /// Slugs of the boards it applies to; every board if empty.
+    #[serde(default)]
+    pub boards: Vec<String>,
+    /// Put in place of each match by `replace`. With `regex`, `$1` and
+    /// `${name}` refer to capture groups.
+    #[serde(default)]
+    pub replacement: String,
+    /// Shown to the poster by `reject` and `ban`.
+    #[serde(default = "default_filter_message")]
+    pub message: String,
+    /// How long `ban` lasts; 0 is until lifted.
+    #[serde(default = "default_ban_hours")]
+    pub ban_hours: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    /// Rewrite the match and let the post through.
+    Replace,
+    /// Turn the post away.
+    Reject,
+    /// Turn the post away and ban the poster's address.
+    Ban,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoardConfig {
+    pub slug: String,
+    pub title: String,
+    #[serde(default)]
+    pub subtitle: String,
+    #[serde(default = "default_max_file_size")]
+    pub max_file_size: i64,
+    #[serde(default = "default_extensions")]
+    pub allowed_extensions: Vec<String>,
+    #[serde(default = "default_name")]
+    pub default_name: String,
+    /// Turn away files that were already posted on this board.
+    #[serde(default)]
+    pub reject_duplicate_files: bool,
+    /// Hide every file behind a spoiler, whatever the poster chose.
+    #[serde(default)]
+    pub require_spoiler: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// `tracing` filter directives, e.g. `info` or `info,tower_http=debug`.
+    pub level: String,
+    pub format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One line per event.
+    Text,
+    /// Multi-line and indented, for reading in a terminal.
+    Pretty,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig { bind: SocketAddr::from(([127, 0, 0, 1], 3000)), behind_proxy: false }
+    }
+}
+
+impl Default for SiteConfig {
+    fn default() -> Self {
+        SiteConfig { title: "ChessBoard".to_string() }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig { per_page: 15, preview_replies: 3, max_image_pixels: 40_000_000, max_files_per_post: 4 }
+    }
+}
+
+impl Default for ThumbnailConfig {
+    fn default() -> Self {
+        ThumbnailConfig {
+            width: 150,
+            height: 150,
+            animated: false,
+            ffmpeg: PathBuf::from("ffmpeg"),
+            ffprobe: PathBuf::from("ffprobe"),
+        }
+    }
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        PathsConfig {
+            static_dir: PathBuf::from("static"),
+            uploads: PathBuf::from("static/uploads"),
+            thumbs: PathBuf::from("static/thumbs"),
+            originals: None,
+        }
+    }
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        S3Config {
+            endpoint: None,
+            bucket: String::new(),
+            region: "us-east-1".to_string(),
+            access_key_id: None,
+            secret_access_key: None,
+        }
+    }
+}
+
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        FeaturesConfig { text_search: true, position_search: true, metrics: true }
+    }
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        ModerationConfig {
+            image_ban_distance: 8,
+            banned_image_message: "This image is not allowed.".to_string(),
+            max_links_per_post: 5,
+        }
+    }
+}
+
+fn default_filter_message() -> String {
+    "Your post was blocked by a filter.".to_string()
+}
+
+fn default_ban_hours() -> u32 {
+    24
+}
+
+fn default_max_file_size() -> i64 {
+    2 * 1024 * 1024
+}
+
+fn default_extensions() -> Vec<String> {
+    ["jpg", "jpeg", "png", "gif", "webp", "webm", "mp4", "mp3", "ogg", "flac"].iter().map(|ext| ext.to_string()).collect()
+}
+
+fn default_name() -> String {
+    "Anonymous".to_string()
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig { level: "info".to_string(), format: LogFormat::Text }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("expected text, pretty or json".to_string()),
+        }
+    }
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(StorageBackend::Local),
+            "s3" => Ok(StorageBackend::S3),
+            _ => Err("expected local or s3".to_string()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Env { var: &'static str, value: String, reason: String },
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "{} is not valid: {}", path.display(), e),
+            ConfigError::Env { var, value, reason } => write!(f, "environment variable {}={:?}: {}", var, value, reason),
+            ConfigError::Invalid(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl Config {
+    /// Reads `path` (or `config.toml` if present), applies environment
+    /// overrides and validates the result.
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        let mut config = match path {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_PATH).exists() => Self::from_file(Path::new(DEFAULT_PATH))?,
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        env_override("CHESSBOARD_BIND", &mut self.server.bind)?;
+        env_override("CHESSBOARD_BEHIND_PROXY", &mut self.server.behind_proxy)?;
+        if let Ok(url) = std::env::var("DATABASE_URL") {
+            self.database.url = Some(url);
+        }
+        env_override("DEV_MODE", &mut self.database.dev_mode)?;
+        env_override("CHESSBOARD_SITE_TITLE", &mut self.site.title)?;
+        env_override("CHESSBOARD_PER_PAGE", &mut self.limits.per_page)?;
+        env_override("CHESSBOARD_PREVIEW_REPLIES", &mut self.limits.preview_replies)?;
+        env_override("CHESSBOARD_MAX_IMAGE_PIXELS", &mut self.limits.max_image_pixels)?;
+        env_override("CHESSBOARD_MAX_FILES_PER_POST", &mut self.limits.max_files_per_post)?;
+        env_override("CHESSBOARD_THUMBNAIL_WIDTH", &mut self.thumbnails.width)?;
+        env_override("CHESSBOARD_THUMBNAIL_HEIGHT", &mut self.thumbnails.height)?;
+        env_override("CHESSBOARD_ANIMATED_THUMBNAILS", &mut self.thumbnails.animated)?;
+        env_override("CHESSBOARD_FFMPEG", &mut self.thumbnails.ffmpeg)?;
+        env_override("CHESSBOARD_FFPROBE", &mut self.thumbnails.ffprobe)?;
+        env_override("CHESSBOARD_STATIC_DIR", &mut self.paths.static_dir)?;
+        env_override("CHESSBOARD_UPLOADS_DIR", &mut self.paths.uploads)?;
+        env_override("CHESSBOARD_THUMBS_DIR", &mut self.paths.thumbs)?;
+        if let Ok(dir) = std::env::var("CHESSBOARD_ORIGINALS_DIR") {
+            self.paths.originals = Some(PathBuf::from(dir));
+        }
+        env_override("CHESSBOARD_STORAGE", &mut self.storage.backend)?;
+        if let Ok(endpoint) = std::env::var("CHESSBOARD_S3_ENDPOINT") {
+            self.storage.s3.endpoint = Some(endpoint);
+        }
+        env_override("CHESSBOARD_S3_BUCKET", &mut self.storage.s3.bucket)?;
+        env_override("CHESSBOARD_S3_REGION", &mut self.storage.s3.region)?;
+        if let Ok(key) = std::env::var("CHESSBOARD_S3_ACCESS_KEY_ID") {
+            self.storage.s3.access_key_id = Some(key);
+        }
+        if let Ok(secret) = std::env::var("CHESSBOARD_S3_SECRET_ACCESS_KEY") {
+            self.storage.s3.secret_access_key = Some(secret);
+        }
+        env_override("CHESSBOARD_TEXT_SEARCH", &mut self.features.text_search)?;
+        env_override("CHESSBOARD_POSITION_SEARCH", &mut self.features.position_search)?;
+        env_override("CHESSBOARD_METRICS", &mut self.features.metrics)?;
+        env_override("CHESSBOARD_IMAGE_BAN_DISTANCE", &mut self.moderation.image_ban_distance)?;
+        env_override("CHESSBOARD_BANNED_IMAGE_MESSAGE", &mut self.moderation.banned_image_message)?;
+        env_override("CHESSBOARD_MAX_LINKS_PER_POST", &mut self.moderation.max_links_per_post)?;
+        env_override("CHESSBOARD_LOG", &mut self.logging.level)?;
+        env_override("CHESSBOARD_LOG_FORMAT", &mut self.logging.format)?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.logging.level) {
+            return invalid(format!("logging.level: {}", e));
+        }
+        if self.database.url.as_deref().is_none_or(str::is_empty) {
+            return invalid("database.url is not set (set it in the config file or DATABASE_URL)".to_string());
+        }
+        if self.site.title.trim().is_empty() {
+            return invalid("site.title must not be empty".to_string());
+        }
+        if !(1..=100).contains(&self.limits.per_page) {
+            return invalid(format!("limits.per_page must be between 1 and 100, got {}", self.limits.per_page));
+        }
+        if self.limits.max_image_pixels == 0 {
+            return invalid("limits.max_image_pixels must be positive".to_string());
+        }
+        if !(1..=16).contains(&self.limits.max_files_per_post) {
+            return invalid(format!("limits.max_files_per_post must be between 1 and 16, got {}", self.limits.max_files_per_post));
+        }
+        for (key, value) in [("thumbnails.width", self.thumbnails.width), ("thumbnails.height", self.thumbnails.height)] {
+            if !(16..=1024).contains(&value) {
+                return invalid(format!("{} must be between 16 and 1024 pixels, got {}", key, value));
+            }
+        }
+        if !self.paths.static_dir.is_dir() {
+            return invalid(format!("paths.static_dir: {} is not a directory", self.paths.static_dir.display()));
+        }
+        let dirs = [
+            ("paths.uploads", Some(&self.paths.uploads)),
+            ("paths.thumbs", Some(&self.paths.thumbs)),
+            ("paths.originals", self.paths.originals.as_ref()),
+        ];
+        // The S3 backend leaves the upload directories unused.
+        let local = self.storage.backend == StorageBackend::Local;
+        for (key, dir) in dirs {
+            let Some(dir) = dir.filter(|_| local) else {
+                continue;
+            };
+            std::fs::create_dir_all(dir)
+                .or_else(|e| invalid(format!("{}: cannot create {}: {}", key, dir.display(), e)))?;
+        }
+        for (key, dir) in [("paths.uploads", &self.paths.uploads), ("paths.thumbs", &self.paths.thumbs)] {
+            if local && *dir == self.paths.static_dir {
+                return invalid(format!("{} must be a directory of its own, not paths.static_dir", key));
+            }
+        }
+        if let Some(originals) = &self.paths.originals {
+            if originals.starts_with(&self.paths.static_dir) {
+                return invalid("paths.originals must not be inside paths.static_dir, which is served".to_string());
+            }
+        }
+        if self.storage.backend == StorageBackend::S3 {
+            if self.storage.s3.bucket.is_empty() {
+                return invalid("storage.s3.bucket must be set for the s3 backend".to_string());
+            }
+            if let Some(endpoint) = &self.storage.s3.endpoint {
+                if url::Url::parse(endpoint).is_err() {
+                    return invalid(format!("storage.s3.endpoint: {:?} is not a URL", endpoint));
+                }
+            }
+        }
+        // Unrelated images are around 32 bits apart; past 16 they start to match.
+        if self.moderation.image_ban_distance > 16 {
+            return invalid(format!(
+                "moderation.image_ban_distance must be at most 16, got {}",
+                self.moderation.image_ban_distance
+            ));
+        }
+        if self.moderation.banned_image_message.trim().is_empty() {
+            return invalid("moderation.banned_image_message must not be empty".to_string());
+        }
+        if let Err(e) = Filters::compile(&self.filters) {
+            return invalid(e);
+        }
+        let mut slugs = HashSet::new();
+        for board in &self.boards {
+            board.validate()?;
+            if !slugs.insert(board.slug.as_str()) {
+                return invalid(format!("board /{}/ is listed twice", board.slug));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BoardConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |msg: String| Err(ConfigError::Invalid(format!("board /{}/: {}", self.slug, msg)));
+        let valid_slug = !self.slug.is_empty()
+            && self.slug.len() <= 16
+            && self.slug.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit());
+        if !valid_slug {
+            return invalid("slug must be 1-16 lowercase letters or digits".to_string());
+        }
+        if ["search", "static", "uploads", "thumbs", "metrics", "healthz", "readyz"].contains(&self.slug.as_str()) {
+            return invalid("slug is reserved for a built-in route".to_string());
+        }
+        if self.title.trim().is_empty() {
+            return invalid("title must not be empty".to_string());
+        }
+        if self.max_file_size <= 0 {
+            return invalid(format!("max_file_size must be positive, got {}", self.max_file_size));
+        }
+        if self.allowed_extensions.is_empty() {
+            return invalid("allowed_extensions must list at least one extension".to_string());
+        }
+        if let Some(ext) = self
+            .allowed_extensions
+            .iter()
+            .find(|ext| ext.is_empty() || !ext.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit()))
+        {
+            return invalid(format!("extension {:?} must be lowercase letters or digits without a dot", ext));
+        }
+        let known = |ext: &str| ImageFormat::from_extension(ext).is_some() || Container::from_extension(ext).is_some();
+        if let Some(ext) = self.allowed_extensions.iter().find(|ext| !known(ext)) {
+            return invalid(format!("extension {:?} is not a format uploads can be checked against", ext));
+        }
+        Ok(())
+    }
+}
+
+fn env_override<T>(var: &'static str, target: &mut T) -> Result<(), ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    if let Ok(value) = std::env::var(var) {
+        *target = value.parse().map_err(|e: T::Err| ConfigError::Env { var, value, reason: e.to_string() })?;
+    }
+    Ok(())
+}
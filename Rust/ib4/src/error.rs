@@ -0,0 +1,169 @@
+use axum::{
+    extract::Request,
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Json, Response},
+};
+use serde::Serialize;
+use std::fmt;
+use std::io;
+
+/// Everything a handler can fail with. Each variant picks its own status
+/// code; the cause is logged and only a safe message reaches the client.
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    Validation(String),
+    /// The request was over its route's size limit.
+    TooLarge(String),
+    Storage(io::Error),
+    Database(sqlx::Error),
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+/// Attached to error responses so `negotiate` can re-render them as JSON.
+#[derive(Clone)]
+struct ErrorInfo {
+    status: StatusCode,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    status: u16,
+    error: &'a str,
+}
+
+impl AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::Storage(e) if e.kind() == io::ErrorKind::StorageFull => StatusCode::INSUFFICIENT_STORAGE,
+            AppError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Database(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND,
+            AppError::Database(sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_)) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            AppError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// What the visitor is told; storage and database details stay in the log.
+    fn public_message(&self) -> String {
+        match self {
+            AppError::NotFound(msg) | AppError::Validation(msg) | AppError::TooLarge(msg) => msg.clone(),
+            AppError::Database(sqlx::Error::RowNotFound) => "Not found".to_string(),
+            AppError::Storage(_) => "The server could not read or write a file. Please try again later.".to_string(),
+            AppError::Database(_) => "The board is having database trouble. Please try again later.".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NotFound(msg) => write!(f, "not found: {}", msg),
+            AppError::Validation(msg) => write!(f, "invalid request: {}", msg),
+            AppError::TooLarge(msg) => write!(f, "too large: {}", msg),
+            AppError::Storage(e) => write!(f, "storage error: {}", e),
+            AppError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        AppError::Database(e)
+    }
+}
+
+impl From<io::Error> for AppError {
+    fn from(e: io::Error) -> Self {
+        AppError::Storage(e)
+    }
+}
+
+/// Only a failing read or write is the server's fault; anything else is an
+/// upload that is corrupt, truncated or not what it claims to be. Decoders
+/// report truncated data as an unexpected end of file.
+impl From<image::ImageError> for AppError {
+    fn from(e: image::ImageError) -> Self {
+        match e {
+            image::ImageError::IoError(e)
+                if !matches!(e.kind(), io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData) =>
+            {
+                AppError::Storage(e)
+            }
+            other => AppError::Validation(format!("The image could not be read: {}", other)),
+        }
+    }
+}
+
+impl From<axum_extra::extract::multipart::MultipartError> for AppError {
+    fn from(e: axum_extra::extract::multipart::MultipartError) -> Self {
+        if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            return AppError::TooLarge("The post is larger than the board accepts".to_string());
+        }
+        AppError::Validation(format!("Malformed upload: {}", e.body_text()))
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            tracing::error!(%status, error = %self, "request failed");
+        } else {
+            tracing::info!(%status, error = %self, "request rejected");
+        }
+        let message = self.public_message();
+        let mut html = crate::page_header(&status.to_string(), "");
+        html.push_str(&format!(
+            "<div class=\"banner\">{} <a href=\"/\">Return to the board list</a></div></body></html>",
+            crate::escape(&message)
+        ));
+        let mut response = (status, Html(html)).into_response();
+        response.extensions_mut().insert(ErrorInfo { status, message });
+        response
+    }
+}
+
+/// Router fallback for URLs no route matches.
+pub async fn not_found() -> AppError {
+    AppError::NotFound("No such page".to_string())
+}
+
+/// Middleware that swaps the HTML error page for a JSON body when the
+/// client asked for JSON and not HTML.
+pub async fn negotiate(request: Request, next: Next) -> Response {
+    let wants_json = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json") && !accept.contains("text/html"));
+    let response = next.run(request).await;
+    match response.extensions().get::<ErrorInfo>() {
+        Some(info) if wants_json => {
+            let body = ErrorBody { status: info.status.as_u16(), error: &info.message };
+            (info.status, Json(body)).into_response()
+        }
+        _ => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unreadable_images_are_the_clients_fault() {
+        let truncated = &crate::tests::png(64, 64)[..200];
+        let e = image::load_from_memory(truncated).unwrap_err();
+        assert_eq!(AppError::from(e).status(), StatusCode::BAD_REQUEST);
+        let e = image::ImageError::IoError(io::Error::other("disk gone"));
+        assert_eq!(AppError::from(e).status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,557 @@
+use crate::config::Config;
+use crate::error::AppResult;
+use crate::media::{self, Container, MediaInfo};
+use crate::storage::{Area, Storage};
+use crate::{monitoring, AppState, SharedState};
+use axum::http::StatusCode;
+use axum_extra::extract::multipart::Field;
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, DynamicImage, Frame, Frames, ImageFormat, ImageReader, ImageResult};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+/// How often unreferenced files are looked for.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long a file stays after its last post is gone. It also covers the
+/// gap between `store` and the post insert that takes the first reference.
+const GRACE: Duration = Duration::from_secs(60 * 60);
+
+/// A stored upload, shared by every post with the same content.
+#[derive(Debug)]
+pub struct StoredFile {
+    pub sha256: Vec<u8>,
+    pub filename: String,
+    pub thumbname: Option<String>,
+    pub size: i64,
+    pub kind: String,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub duration_ms: Option<i32>,
+}
+
+pub fn sha256(bytes: &[u8]) -> Vec<u8> {
+    Sha256::digest(bytes).to_vec()
+}
+
+/// Leading bytes of an upload kept in memory, enough to tell its format.
+const HEAD_LEN: usize = 64;
+
+/// An upload streamed to a temporary file, which is removed when this is
+/// dropped.
+pub struct Spooled {
+    file: NamedTempFile,
+    pub size: usize,
+    pub sha256: Vec<u8>,
+    /// The first `HEAD_LEN` bytes.
+    pub head: Vec<u8>,
+}
+
+impl Spooled {
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+
+    /// Width and height from the image header, like `dimensions`, reading
+    /// no further into the file than the header.
+    pub fn dimensions(&self, format: ImageFormat) -> Option<(u32, u32)> {
+        let file = BufReader::new(self.file.reopen().ok()?);
+        ImageReader::with_format(file, format).into_dimensions().ok()
+    }
+}
+
+/// Streams `field` to a temporary file chunk by chunk, hashing it on the
+/// way. `None` means it held more than `limit` bytes: nothing past the
+/// limit is written, and the rest is read and dropped, up to the route's
+/// body limit, so the client is still listening for the answer.
+pub async fn spool(field: &mut Field, limit: usize) -> AppResult<Option<Spooled>> {
+    let file = NamedTempFile::new()?;
+    let mut writer = tokio::fs::File::from_std(file.reopen()?);
+    let mut size = 0;
+    let mut hasher = Sha256::new();
+    let mut head = Vec::with_capacity(HEAD_LEN);
+    loop {
+        let chunk = match field.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            // The body limit cut the request off inside this file.
+            Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        size += chunk.len();
+        if size > limit {
+            continue;
+        }
+        hasher.update(&chunk);
+        let wanted = HEAD_LEN.saturating_sub(head.len()).min(chunk.len());
+        head.extend_from_slice(&chunk[..wanted]);
+        writer.write_all(&chunk).await?;
+    }
+    if size > limit {
+        return Ok(None);
+    }
+    writer.flush().await?;
+    Ok(Some(Spooled { file, size, sha256: hasher.finalize().to_vec(), head }))
+}
+
+/// Reads a field that is turned away to its end without keeping any of it.
+pub async fn discard(field: &mut Field) {
+    while let Ok(Some(_)) = field.chunk().await {}
+}
+
+/// An accepted upload, ready for `store`.
+pub enum Upload {
+    /// Stripped of metadata, with the upload as received and its
+    /// perceptual hash if it decoded.
+    Image { bytes: Vec<u8>, original: Vec<u8>, format: ImageFormat, phash: Option<i64> },
+    /// Stored as received, straight from its spool file.
+    Media { spooled: Spooled, container: Container },
+}
+
+impl Upload {
+    fn format(&self) -> Format {
+        match self {
+            Upload::Image { format, .. } => Format::Image(*format),
+            Upload::Media { container, .. } => Format::Media(*container),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Upload::Image { bytes, .. } => bytes.len(),
+            Upload::Media { spooled, .. } => spooled.size,
+        }
+    }
+}
+
+/// GIF quantizer speed for animated thumbnails, from 1 (best) to 30.
+const GIF_SPEED: i32 = 10;
+
+/// Longest original filename kept, in characters.
+const MAX_NAME_CHARS: usize = 100;
+
+/// The client's name for an upload, fit to store and show: the last path
+/// component only (some browsers send the whole path), without control
+/// characters, runs of whitespace or leading dots, and shortened to
+/// `MAX_NAME_CHARS` with the extension kept. An empty result becomes
+/// `file.<ext>`.
+pub fn sanitize_name(name: &str, ext: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or("");
+    let cleaned: String = base.chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+    let cleaned = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+    let cleaned = cleaned.trim_start_matches('.');
+    if cleaned.is_empty() {
+        return format!("file.{}", ext);
+    }
+    if cleaned.chars().count() <= MAX_NAME_CHARS {
+        return cleaned.to_string();
+    }
+    let (stem, suffix) = match cleaned.rsplit_once('.') {
+        Some((stem, suffix)) if suffix.chars().count() <= 10 => (stem, format!(".{}", suffix)),
+        _ => (cleaned, String::new()),
+    };
+    let keep = MAX_NAME_CHARS - suffix.chars().count();
+    let stem: String = stem.chars().take(keep).collect();
+    format!("{}{}", stem.trim_end(), suffix)
+}
+
+/// `Content-Disposition` value that saves a download under `name`: a plain
+/// ASCII `filename` for old clients and the exact name, UTF-8 and
+/// percent-encoded, in `filename*`.
+pub fn content_disposition(name: &str) -> String {
+    let ascii: String = name
+        .chars()
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+    let mut encoded = String::new();
+    for byte in name.bytes() {
+        // RFC 5987 attr-char; everything else is percent-encoded.
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", ascii, encoded)
+}
+
+/// What an upload is, going by its leading bytes. The client's filename
+/// and Content-Type are not trusted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Image(ImageFormat),
+    Media(Container),
+}
+
+impl Format {
+    pub fn detect(bytes: &[u8]) -> Option<Format> {
+        match image::guess_format(bytes) {
+            Ok(format) => Some(Format::Image(format)),
+            Err(_) => Container::sniff(bytes).map(Format::Media),
+        }
+    }
+
+    /// Whether `ext`, as claimed by the client, names this format.
+    pub fn has_extension(self, ext: &str) -> bool {
+        match self {
+            Format::Image(format) => ImageFormat::from_extension(ext) == Some(format),
+            Format::Media(container) => Container::from_extension(ext) == Some(container),
+        }
+    }
+
+    /// Extension the file is stored under.
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Image(format) => format.extensions_str().first().copied().unwrap_or("bin"),
+            Format::Media(container) => container.extension(),
+        }
+    }
+}
+
+/// Width and height from the image header, read without decoding pixels,
+/// so oversized images can be turned away before they are decoded.
+pub fn dimensions(bytes: &[u8], format: ImageFormat) -> Option<(u32, u32)> {
+    ImageReader::with_format(Cursor::new(bytes), format).into_dimensions().ok()
+}
+
+/// Thread and post number of a post on the board that already carries this
+/// file, for boards that turn duplicates away.
+pub async fn find_on_board(pool: &PgPool, board_id: i32, sha256: &[u8]) -> AppResult<Option<(i64, i64)>> {
+    let post = sqlx::query!(
+        r#"
+        SELECT p.thread_id, p.post_no
+        FROM post_files pf
+        JOIN posts p ON p.id = pf.post_id
+        WHERE p.board_id = $1 AND pf.file_sha256 = $2
+        ORDER BY p.post_no
+        LIMIT 1
+        "#,
+        board_id,
+        sha256
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(post.map(|post| (post.thread_id, post.post_no)))
+}
+
+/// Returns the stored copy of `upload`, writing the file and its thumbnail
+/// only if this content has not been uploaded before. The upload as
+/// received is kept too if `paths.originals` is set. The reference is
+/// counted when a `post_files` row with this `sha256` is inserted.
+pub async fn store(state: &AppState, board: &str, upload: &Upload, sha256: &[u8]) -> AppResult<StoredFile> {
+    let (pool, config, storage) = (&state.db, &state.config, state.storage.as_ref());
+    let existing = sqlx::query_as!(
+        StoredFile,
+        r#"
+        UPDATE files SET last_used_at = now() WHERE sha256 = $1
+        RETURNING sha256, filename, thumbname, size, kind, width, height, duration_ms
+        "#,
+        sha256
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(file) = existing {
+        monitoring::upload_deduplicated(board);
+        return Ok(file);
+    }
+    let uuid = Uuid::new_v4().to_string();
+    let filename = format!("{}.{}", uuid, upload.format().extension());
+    let keep_original = config.paths.originals.is_some();
+    let phash = match upload {
+        Upload::Image { phash, .. } => *phash,
+        Upload::Media { .. } => None,
+    };
+    let info = match upload {
+        Upload::Image { bytes, original, format, .. } => {
+            storage.put(Area::Uploads, &filename, bytes.clone()).await?;
+            if keep_original {
+                storage.put(Area::Originals, &filename, original.clone()).await?;
+            }
+            let (width, height) = dimensions(bytes, *format).unzip();
+            MediaInfo {
+                kind: "image",
+                width: width.map(|w| w as i32),
+                height: height.map(|h| h as i32),
+                duration_ms: None,
+                thumbname: make_thumbnail(config, storage, bytes, *format, &uuid).await,
+            }
+        }
+        Upload::Media { spooled, container } => {
+            storage.put_file(Area::Uploads, &filename, spooled.path()).await?;
+            if keep_original {
+                storage.put_file(Area::Originals, &filename, spooled.path()).await?;
+            }
+            media::inspect(config, storage, *container, spooled.path(), &uuid).await
+        }
+    };
+    monitoring::upload_stored(board, upload.size());
+    let thumbname = info.thumbname;
+    // An identical upload may have been stored since the lookup above; the
+    // first row wins and this copy is dropped.
+    let file = sqlx::query_as!(
+        StoredFile,
+        r#"
+        INSERT INTO files (sha256, filename, thumbname, size, kind, width, height, duration_ms, phash)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (sha256) DO UPDATE SET last_used_at = now()
+        RETURNING sha256, filename, thumbname, size, kind, width, height, duration_ms
+        "#,
+        sha256,
+        filename,
+        thumbname,
+        upload.size() as i64,
+        info.kind,
+        info.width,
+        info.height,
+        info.duration_ms,
+        phash
+    )
+    .fetch_one(pool)
+    .await?;
+    if file.filename != filename {
+        remove(config, storage, &filename, thumbname.as_deref()).await;
+    }
+    Ok(file)
+}
+
+/// Saves a thumbnail named after `uuid` and returns its name, or `None` if
+/// the upload could not be thumbnailed; the post then links the file alone.
+async fn make_thumbnail(config: &Config, storage: &dyn Storage, bytes: &[u8], format: ImageFormat, uuid: &str) -> Option<String> {
+    let started = Instant::now();
+    let animated = if config.thumbnails.animated {
+        animated_thumbnail(config, bytes, format, uuid)
+    } else {
+        Ok(None)
+    };
+    let made = match animated {
+        Ok(Some(thumb)) => Ok(thumb),
+        Ok(None) => still_thumbnail(config, bytes, format, uuid),
+        Err(e) => Err(e),
+    };
+    let saved = match made {
+        Ok((thumbname, thumb)) => storage.put(Area::Thumbs, &thumbname, thumb).await.map(|()| thumbname).map_err(Into::into),
+        Err(e) => Err(e),
+    };
+    match saved {
+        Ok(thumbname) => {
+            monitoring::thumbnail_made(started.elapsed());
+            Some(thumbname)
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "could not make a thumbnail");
+            monitoring::thumbnail_failed();
+            None
+        }
+    }
+}
+
+/// First frame, shrunk to fit, with its name. Images with transparent
+/// pixels get a PNG thumbnail; JPEG has no alpha and would paint them black.
+fn still_thumbnail(config: &Config, bytes: &[u8], format: ImageFormat, uuid: &str) -> ImageResult<(String, Vec<u8>)> {
+    let img = fit(image::load_from_memory_with_format(bytes, format)?, config);
+    let transparent = img.color().has_alpha() && img.to_rgba8().pixels().any(|pixel| pixel[3] < u8::MAX);
+    let (thumbname, img, format) = if transparent {
+        (format!("{}_thumb.png", uuid), img, ImageFormat::Png)
+    } else {
+        (format!("{}_thumb.jpg", uuid), DynamicImage::ImageRgb8(img.to_rgb8()), ImageFormat::Jpeg)
+    };
+    let mut thumb = Vec::new();
+    img.write_to(&mut Cursor::new(&mut thumb), format)?;
+    Ok((thumbname, thumb))
+}
+
+/// Animated GIF thumbnail of an animated GIF, APNG or WebP. `None` means
+/// the upload is a still image, or its frames together hold more than
+/// `limits.max_image_pixels`; it then gets a still thumbnail instead.
+fn animated_thumbnail(config: &Config, bytes: &[u8], format: ImageFormat, uuid: &str) -> ImageResult<Option<(String, Vec<u8>)>> {
+    let Some(frames) = frames(bytes, format)? else {
+        return Ok(None);
+    };
+    // Every frame is decoded to the full canvas, so a small file with
+    // thousands of frames is as much a bomb as one huge frame.
+    let mut budget = config.limits.max_image_pixels;
+    let mut thumbs = Vec::new();
+    for frame in frames {
+        let frame = frame?;
+        let (width, height) = frame.buffer().dimensions();
+        let Some(left) = budget.checked_sub(u64::from(width) * u64::from(height)) else {
+            return Ok(None);
+        };
+        budget = left;
+        let delay = frame.delay();
+        let thumb = fit(DynamicImage::ImageRgba8(frame.into_buffer()), config).to_rgba8();
+        thumbs.push(Frame::from_parts(thumb, 0, 0, delay));
+    }
+    if thumbs.len() < 2 {
+        return Ok(None);
+    }
+    let mut thumb = Vec::new();
+    let mut encoder = GifEncoder::new_with_speed(&mut thumb, GIF_SPEED);
+    encoder.set_repeat(Repeat::Infinite)?;
+    encoder.encode_frames(thumbs)?;
+    drop(encoder);
+    Ok(Some((format!("{}_thumb.gif", uuid), thumb)))
+}
+
+/// Frames of an animated GIF, APNG or WebP, or `None` for other images.
+fn frames(bytes: &[u8], format: ImageFormat) -> ImageResult<Option<Frames<'_>>> {
+    let reader = Cursor::new(bytes);
+    Ok(match format {
+        ImageFormat::Gif => Some(GifDecoder::new(reader)?.into_frames()),
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(reader)?;
+            if decoder.is_apng()? {
+                Some(decoder.apng()?.into_frames())
+            } else {
+                None
+            }
+        }
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(reader)?;
+            if decoder.has_animation() {
+                Some(decoder.into_frames())
+            } else {
+                None
+            }
+        }
+        _ => None,
+    })
+}
+
+/// Shrinks `img` to fit the configured box, keeping its aspect ratio.
+/// Images that already fit are not enlarged.
+fn fit(img: DynamicImage, config: &Config) -> DynamicImage {
+    let (width, height) = (config.thumbnails.width, config.thumbnails.height);
+    if img.width() <= width && img.height() <= height {
+        img
+    } else {
+        img.thumbnail(width, height)
+    }
+}
+
+/// Runs `sweep` every `SWEEP_INTERVAL` for as long as the server is up.
+pub async fn sweep_periodically(state: SharedState) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = sweep(&state.db, &state.config, state.storage.as_ref()).await {
+            tracing::error!(error = %e, "sweeping unreferenced files failed");
+        }
+    }
+}
+
+/// Deletes files no post has referenced for `GRACE`.
+async fn sweep(pool: &PgPool, config: &Config, storage: &dyn Storage) -> AppResult<()> {
+    let removed = sqlx::query!(
+        r#"
+        DELETE FROM files
+        WHERE ref_count = 0 AND last_used_at < now() - make_interval(secs => $1)
+        RETURNING filename, thumbname
+        "#,
+        GRACE.as_secs_f64()
+    )
+    .fetch_all(pool)
+    .await?;
+    for file in &removed {
+        remove(config, storage, &file.filename, file.thumbname.as_deref()).await;
+    }
+    if !removed.is_empty() {
+        tracing::info!(files = removed.len(), "removed unreferenced files");
+    }
+    Ok(())
+}
+
+async fn remove(config: &Config, storage: &dyn Storage, filename: &str, thumbname: Option<&str>) {
+    // Only uploads stored while originals were kept have one; deleting a
+    // missing file is not an error.
+    let files = std::iter::once((Area::Uploads, filename))
+        .chain(thumbname.map(|thumb| (Area::Thumbs, thumb)))
+        .chain(config.paths.originals.as_ref().map(|_| (Area::Originals, filename)));
+    for (area, name) in files {
+        if let Err(e) = storage.delete(area, name).await {
+            tracing::warn!(?area, name, error = %e, "could not remove file");
+        }
+    }
+}
+
+/// Links uploads from before deduplication to `files`, hashing each one
+/// once. Posts whose content was uploaded twice are pointed at one copy and
+/// the other is deleted. Files missing from disk are left unlinked.
+pub async fn adopt_existing(pool: &PgPool, config: &Config, storage: &dyn Storage) -> AppResult<()> {
+    let legacy = sqlx::query!(
+        r#"
+        SELECT DISTINCT filename, thumbname
+        FROM post_files
+        WHERE file_sha256 IS NULL
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    for post in &legacy {
+        let bytes = match storage.get(Area::Uploads, &post.filename).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                tracing::warn!(filename = %post.filename, error = %e, "upload missing, not deduplicated");
+                continue;
+            }
+        };
+        let sha256 = sha256(&bytes);
+        // Uploads from back then were all images.
+        let (width, height) = image::guess_format(&bytes)
+            .ok()
+            .and_then(|format| dimensions(&bytes, format))
+            .map(|(width, height)| (width as i32, height as i32))
+            .unzip();
+        let mut tx = pool.begin().await?;
+        let file = sqlx::query_as!(
+            StoredFile,
+            r#"
+            INSERT INTO files (sha256, filename, thumbname, size, width, height)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (sha256) DO UPDATE SET last_used_at = now()
+            RETURNING sha256, filename, thumbname, size, kind, width, height, duration_ms
+            "#,
+            sha256,
+            post.filename,
+            post.thumbname,
+            bytes.len() as i64,
+            width,
+            height
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query!(
+            r#"
+            UPDATE post_files
+            SET file_sha256 = $1, filename = $2, thumbname = $3, size = $4, kind = $5, width = $6, height = $7
+            WHERE filename = $8 AND file_sha256 IS NULL
+            "#,
+            file.sha256,
+            file.filename,
+            file.thumbname,
+            file.size,
+            file.kind,
+            file.width,
+            file.height,
+            post.filename
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        if file.filename != post.filename {
+            remove(config, storage, &post.filename, post.thumbname.as_deref()).await;
+        }
+    }
+    if !legacy.is_empty() {
+        tracing::info!(files = legacy.len(), "linked existing uploads to the files table");
+    }
+    Ok(())
+}
@@ -0,0 +1,277 @@
+use crate::config::{FilterAction, FilterConfig};
+use regex::{NoExpand, Regex, RegexBuilder};
+use std::sync::LazyLock;
+
+/// Compiled regexes past this size are refused, so one filter cannot make
+/// every post slow to check.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// The `[[filters]]` list, compiled once at startup.
+pub struct Filters {
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    matcher: Regex,
+    /// Whether `replacement` is taken as written, without `$` expansion.
+    literal: bool,
+    action: FilterAction,
+    boards: Vec<String>,
+    replacement: String,
+    message: String,
+    ban_hours: u32,
+}
+
+/// Why a filter turned a post away.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Blocked {
+    Rejected { message: String },
+    /// `hours` is 0 for a ban that lasts until lifted.
+    Banned { message: String, hours: u32 },
+}
+
+impl Filters {
+    /// Fails with the first rule that does not compile, by its place in
+    /// the list.
+    pub fn compile(configs: &[FilterConfig]) -> Result<Filters, String> {
+        let rules = configs
+            .iter()
+            .enumerate()
+            .map(|(i, config)| Rule::compile(config).map_err(|e| format!("filters[{}] ({:?}): {}", i, config.pattern, e)))
+            .collect::<Result<_, _>>()?;
+        Ok(Filters { rules })
+    }
+
+    /// `text` with every `replace` rule for `board` applied, or the first
+    /// `reject` or `ban` rule that matches. Rules run in order, each on the
+    /// text the ones before it left.
+    pub fn apply(&self, board: &str, text: &str) -> Result<String, Blocked> {
+        let mut text = text.to_string();
+        for rule in self.rules.iter().filter(|rule| rule.applies_to(board)) {
+            match rule.action {
+                FilterAction::Replace => {
+                    let replaced = if rule.literal {
+                        rule.matcher.replace_all(&text, NoExpand(&rule.replacement))
+                    } else {
+                        rule.matcher.replace_all(&text, rule.replacement.as_str())
+                    };
+                    text = replaced.into_owned();
+                }
+                FilterAction::Reject if rule.matcher.is_match(&text) => {
+                    return Err(Blocked::Rejected { message: rule.message.clone() });
+                }
+                FilterAction::Ban if rule.matcher.is_match(&text) => {
+                    return Err(Blocked::Banned { message: rule.message.clone(), hours: rule.ban_hours });
+                }
+                FilterAction::Reject | FilterAction::Ban => {}
+            }
+        }
+        Ok(text)
+    }
+}
+
+impl Rule {
+    fn compile(config: &FilterConfig) -> Result<Rule, String> {
+        if config.pattern.trim().is_empty() {
+            return Err("pattern must not be empty".to_string());
+        }
+        if config.action != FilterAction::Replace && config.message.trim().is_empty() {
+            return Err("message must not be empty".to_string());
+        }
+        let source = if config.regex { config.pattern.clone() } else { literal_pattern(&config.pattern) };
+        let matcher = RegexBuilder::new(&source)
+            .case_insensitive(!config.regex)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .build()
+            .map_err(|e| e.to_string())?;
+        // Such a pattern matches between every two characters.
+        if matcher.is_match("") {
+            return Err("pattern matches empty text".to_string());
+        }
+        Ok(Rule {
+            matcher,
+            literal: !config.regex,
+            action: config.action,
+            boards: config.boards.clone(),
+            replacement: config.replacement.clone(),
+            message: config.message.clone(),
+            ban_hours: config.ban_hours,
+        })
+    }
+
+    fn applies_to(&self, board: &str) -> bool {
+        self.boards.is_empty() || self.boards.iter().any(|slug| slug == board)
+    }
+}
+
+/// A regex for `phrase` as a whole: `\b` on each side that starts or ends
+/// with a word character, so "ass" leaves "class" alone, and any run of
+/// whitespace where it has one.
+fn literal_pattern(phrase: &str) -> String {
+    let phrase = phrase.trim();
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let words: Vec<String> = phrase.split_whitespace().map(regex::escape).collect();
+    let mut pattern = words.join(r"\s+");
+    if is_word(phrase.chars().next()) {
+        pattern.insert_str(0, r"\b");
+    }
+    if is_word(phrase.chars().last()) {
+        pattern.push_str(r"\b");
+    }
+    pattern
+}
+
+static LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)\b(?:https?://|www\.)\S").unwrap());
+
+/// Links in `text`: anything starting with `http://`, `https://` or `www.`.
+pub fn count_links(text: &str) -> usize {
+    LINK.find_iter(text).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(pattern: &str, action: FilterAction) -> FilterConfig {
+        FilterConfig {
+            pattern: pattern.to_string(),
+            regex: false,
+            action,
+            boards: Vec::new(),
+            replacement: String::new(),
+            message: "blocked".to_string(),
+            ban_hours: 24,
+        }
+    }
+
+    fn replace(pattern: &str, replacement: &str) -> FilterConfig {
+        FilterConfig { replacement: replacement.to_string(), ..filter(pattern, FilterAction::Replace) }
+    }
+
+    fn regex(pattern: &str, action: FilterAction) -> FilterConfig {
+        FilterConfig { regex: true, ..filter(pattern, action) }
+    }
+
+    fn compile(configs: Vec<FilterConfig>) -> Filters {
+        Filters::compile(&configs).unwrap()
+    }
+
+    #[test]
+    fn no_filters_pass_text_unchanged() {
+        let filters = compile(Vec::new());
+        assert_eq!(filters.apply("chess", "Nf3 is fine"), Ok("Nf3 is fine".to_string()));
+    }
+
+    #[test]
+    fn literal_replace_ignores_case() {
+        let filters = compile(vec![replace("blunder", "brilliancy")]);
+        assert_eq!(
+            filters.apply("chess", "What a Blunder, a BLUNDER!"),
+            Ok("What a brilliancy, a brilliancy!".to_string())
+        );
+    }
+
+    #[test]
+    fn literal_matches_whole_words_only() {
+        let filters = compile(vec![replace("ass", "***")]);
+        assert_eq!(filters.apply("chess", "classic ass pass"), Ok("classic *** pass".to_string()));
+    }
+
+    #[test]
+    fn literal_phrase_matches_any_whitespace() {
+        let filters = compile(vec![filter("free  coins", FilterAction::Reject)]);
+        assert!(filters.apply("chess", "get FREE\n coins now").is_err());
+        assert!(filters.apply("chess", "free chess coins").is_ok());
+    }
+
+    #[test]
+    fn literal_without_word_edges_matches_inside_words() {
+        let filters = compile(vec![replace("$$$", "money")]);
+        assert_eq!(filters.apply("chess", "win$$$now"), Ok("winmoneynow".to_string()));
+    }
+
+    #[test]
+    fn literal_is_not_a_regex() {
+        let filters = compile(vec![filter("e4.e5", FilterAction::Reject)]);
+        assert!(filters.apply("chess", "e4 e5").is_ok());
+        assert!(filters.apply("chess", "1. e4.e5").is_err());
+    }
+
+    #[test]
+    fn literal_replacement_is_not_expanded() {
+        let filters = compile(vec![replace("cheap", "$1")]);
+        assert_eq!(filters.apply("chess", "cheap"), Ok("$1".to_string()));
+    }
+
+    #[test]
+    fn regex_replacement_expands_groups() {
+        let filters = compile(vec![FilterConfig {
+            replacement: "${1}-${2}".to_string(),
+            ..regex(r"(\d+)x(\d+)", FilterAction::Replace)
+        }]);
+        assert_eq!(filters.apply("chess", "a 3x4 board"), Ok("a 3-4 board".to_string()));
+    }
+
+    #[test]
+    fn regex_is_case_sensitive_unless_asked() {
+        let filters = compile(vec![regex("casino", FilterAction::Reject)]);
+        assert!(filters.apply("chess", "CASINO").is_ok());
+        let filters = compile(vec![regex("(?i)casino", FilterAction::Reject)]);
+        assert!(filters.apply("chess", "CASINO").is_err());
+    }
+
+    #[test]
+    fn reject_reports_its_message() {
+        let filters = compile(vec![FilterConfig { message: "No spam".to_string(), ..filter("spam", FilterAction::Reject) }]);
+        assert_eq!(filters.apply("chess", "spam"), Err(Blocked::Rejected { message: "No spam".to_string() }));
+    }
+
+    #[test]
+    fn ban_reports_its_duration() {
+        let filters = compile(vec![FilterConfig { ban_hours: 0, ..filter("spam", FilterAction::Ban) }]);
+        assert_eq!(filters.apply("chess", "spam"), Err(Blocked::Banned { message: "blocked".to_string(), hours: 0 }));
+    }
+
+    #[test]
+    fn first_blocking_rule_wins() {
+        let filters = compile(vec![filter("spam", FilterAction::Reject), filter("spam", FilterAction::Ban)]);
+        assert!(matches!(filters.apply("chess", "spam"), Err(Blocked::Rejected { .. })));
+    }
+
+    #[test]
+    fn rules_see_earlier_replacements() {
+        let filters = compile(vec![replace("spam", "ham"), filter("spam", FilterAction::Reject)]);
+        assert_eq!(filters.apply("chess", "spam"), Ok("ham".to_string()));
+        let filters = compile(vec![replace("spam", "casino"), filter("casino", FilterAction::Reject)]);
+        assert!(filters.apply("chess", "spam").is_err());
+    }
+
+    #[test]
+    fn board_list_limits_a_rule() {
+        let filters = compile(vec![FilterConfig { boards: vec!["b".to_string()], ..replace("bad", "good") }]);
+        assert_eq!(filters.apply("b", "bad"), Ok("good".to_string()));
+        assert_eq!(filters.apply("chess", "bad"), Ok("bad".to_string()));
+    }
+
+    #[test]
+    fn invalid_rules_are_refused() {
+        assert!(Filters::compile(&[filter("  ", FilterAction::Reject)]).is_err());
+        assert!(Filters::compile(&[regex("(unclosed", FilterAction::Reject)]).is_err());
+        assert!(Filters::compile(&[regex("a*", FilterAction::Replace)]).is_err());
+        assert!(Filters::compile(&[FilterConfig { message: String::new(), ..filter("x", FilterAction::Ban) }]).is_err());
+    }
+
+    #[test]
+    fn compile_error_names_the_rule() {
+        let error = Filters::compile(&[replace("ok", ""), regex("(", FilterAction::Reject)]).err().unwrap();
+        assert!(error.starts_with("filters[1]"), "{}", error);
+    }
+
+    #[test]
+    fn links_are_counted() {
+        assert_eq!(count_links("no links, just e4"), 0);
+        assert_eq!(count_links("see https://lichess.org and http://x.y or WWW.example.com"), 3);
+        assert_eq!(count_links("https:// alone"), 0);
+        assert_eq!(count_links("awww. nice"), 0);
+    }
+}
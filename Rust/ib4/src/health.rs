@@ -0,0 +1,61 @@
+use crate::storage::{Area, Storage};
+use crate::SharedState;
+use axum::{extract::State, http::StatusCode};
+use sqlx::Connection;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// How long a readiness probe waits for a database connection; the pool's
+/// own acquire timeout is far longer than any orchestrator will wait.
+const DB_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Liveness: the process is up and serving requests.
+pub async fn healthz() -> &'static str {
+    "ok\n"
+}
+
+/// Readiness: Postgres answers and upload and thumbnail storage accept new
+/// files. Answers 503 listing whichever check failed.
+pub async fn readyz(State(state): State<SharedState>) -> (StatusCode, String) {
+    let database = match tokio::time::timeout(DB_TIMEOUT, async {
+        let mut conn = state.db.acquire().await?;
+        conn.ping().await
+    })
+    .await
+    {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err(format!("no connection within {}s", DB_TIMEOUT.as_secs())),
+    };
+    report(&[
+        ("database", database),
+        ("uploads", writable(state.storage.as_ref(), Area::Uploads).await),
+        ("thumbs", writable(state.storage.as_ref(), Area::Thumbs).await),
+    ])
+}
+
+/// Creates and removes a probe file, which catches read-only mounts,
+/// permission changes and unreachable buckets that a metadata check would
+/// miss.
+async fn writable(storage: &dyn Storage, area: Area) -> Result<(), String> {
+    static PROBES: AtomicU64 = AtomicU64::new(0);
+    let probe = format!(".readyz-{}-{}", std::process::id(), PROBES.fetch_add(1, Ordering::Relaxed));
+    storage.put(area, &probe, Vec::new()).await.map_err(|e| e.to_string())?;
+    storage.delete(area, &probe).await.map_err(|e| e.to_string())
+}
+
+fn report(checks: &[(&str, Result<(), String>)]) -> (StatusCode, String) {
+    let mut body = String::new();
+    let mut ready = true;
+    for (name, result) in checks {
+        match result {
+            Ok(()) => body.push_str(&format!("{}: ok\n", name)),
+            Err(e) => {
+                tracing::warn!(check = name, error = %e, "not ready");
+                body.push_str(&format!("{}: {}\n", name, e));
+                ready = false;
+            }
+        }
+    }
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, body)
+}
@@ -1,59 +1,402 @@
+mod bans;
+mod chess;
+mod config;
+mod dates;
+mod error;
+mod files;
+mod filters;
+mod health;
+mod logging;
+mod media;
+mod metadata;
+mod monitoring;
+mod pgn;
+mod phash;
+mod storage;
+#[cfg(test)]
+mod tests;
+
 use axum::{
-    response::{Html, Redirect},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
     routing::get,
     Router,
-    extract::{Path, Query},
+    extract::{ConnectInfo, DefaultBodyLimit, Path, Query, Request},
+    handler::Handler,
 };
 use axum_extra::extract::Multipart;
-use chrono::Utc;
-use image::{imageops::FilterType, ImageFormat};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Parser, Subcommand};
+use config::{Config, FeaturesConfig};
+use dates::Viewer;
+use error::{AppError, AppResult};
+use metrics_exporter_prometheus::PrometheusHandle;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::net::TcpListener;
 use tower_http::services::ServeDir;
-use uuid::Uuid;
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
 use regex::Regex;
-use sqlx::{PgPool, Executor};
-use std::env;
+use sqlx::types::Json;
+use sqlx::{PgConnection, PgPool, Executor};
+use std::path::PathBuf;
 use dotenvy::dotenv;
+use files::Format;
+use filters::{Blocked, Filters};
+use std::net::{IpAddr, SocketAddr};
+use storage::{Area, Storage};
 use url::Url;
 #[derive(Clone, Debug, Serialize, Deserialize, sqlx::FromRow)]
 struct Post {
     id: i64,
+    board_id: i32,
+    post_no: i64,
     thread_id: i64,
-    bump_timestamp: i64,
     name: String,
     subject: Option<String>,
     message: Option<String>,
-    filename: Option<String>,
+    /// Attachments in upload order, aggregated from `post_files`.
+    files: Json<Vec<PostFile>>,
+    created_at: DateTime<Utc>,
+    fen: Option<String>,
+    pgn: Option<String>,
+}
+/// One attachment of a post, as kept in its `post_files` row.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct PostFile {
+    position: i16,
+    filename: String,
     thumbname: Option<String>,
-    time: String,
+    original_name: String,
+    size: Option<i64>,
+    /// `image`, `video` or `audio`.
+    kind: String,
+    width: Option<i32>,
+    height: Option<i32>,
+    duration_ms: Option<i32>,
+    /// Shown behind the spoiler image until clicked.
+    spoiler: bool,
+}
+/// A thread as shown on a board page.
+#[derive(Debug)]
+struct ThreadPreview {
+    op: Post,
+    /// The last few replies, oldest first.
+    replies: Vec<Post>,
+    reply_count: i64,
+    image_count: i64,
+}
+#[derive(Clone, Debug, sqlx::FromRow)]
+struct Board {
+    id: i32,
+    slug: String,
+    title: String,
+    subtitle: String,
+    max_file_size: i64,
+    allowed_extensions: Vec<String>,
+    default_name: String,
+    reject_duplicate_files: bool,
+    require_spoiler: bool,
+}
+#[derive(Debug, sqlx::FromRow)]
+struct BoardListing {
+    slug: String,
+    title: String,
+    subtitle: String,
+    threads: i64,
+    posts: i64,
+}
+#[derive(Debug, sqlx::FromRow)]
+struct SearchHit {
+    board: String,
+    post_no: i64,
+    thread_id: i64,
+    name: String,
+    created_at: DateTime<Utc>,
+    thumbname: Option<String>,
+    spoiler: bool,
+    subject_hl: String,
+    message_hl: String,
+}
+struct AppState {
+    db: PgPool,
+    config: Config,
+    /// Set when `features.metrics` is on.
+    metrics: Option<PrometheusHandle>,
+    storage: Arc<dyn Storage>,
+    filters: Filters,
+}
+type SharedState = Arc<AppState>;
+/// Room for a post's text fields, and all a reply may send.
+const TEXT_FIELDS_LIMIT: usize = 1024 * 1024;
+#[derive(Parser)]
+#[command(about = "ChessBoard imageboard server")]
+struct Cli {
+    /// TOML configuration file [default: config.toml, if present]
+    #[arg(long, value_name = "FILE", global = true)]
+    config: Option<PathBuf>,
+    /// Runs the server if not given.
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+#[derive(Subcommand)]
+enum Command {
+    /// Ban images, and resized or recompressed copies of them, from being posted
+    BanImage {
+        /// Image files, or uploads by the name in their /uploads/ URL
+        #[arg(required = true)]
+        images: Vec<String>,
+        /// Kept with the ban, for moderators
+        #[arg(long, default_value = "")]
+        reason: String,
+    },
+    /// Lift image bans, by the hashes ban-image printed
+    UnbanImage {
+        #[arg(required = true)]
+        hashes: Vec<String>,
+    },
+    /// Lift every ban on the given poster addresses
+    Unban {
+        #[arg(required = true)]
+        ips: Vec<IpAddr>,
+    },
 }
-type SharedState = Arc<PgPool>;
 #[tokio::main]
 async fn main() {
     dotenv().ok();
-    let dev_mode = env::var("DEV_MODE").unwrap_or_default() == "true";
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    if dev_mode {
+    let cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("Configuration error: {}", e);
+        std::process::exit(1);
+    });
+    logging::init(&config.logging);
+    if let Some(command) = cli.command {
+        if let Err(e) = run_command(command, &config).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let metrics = config.features.metrics.then(monitoring::install);
+    let database_url = config.database.url.clone().expect("validated by Config::load");
+    if config.database.dev_mode {
         reset_database(&database_url).await;
     }
     let pool = PgPool::connect(&database_url).await.expect("Failed to connect to Postgres");
-    if dev_mode {
+    if config.database.dev_mode {
         seed_database(&pool).await;
     }
-    let state = Arc::new(pool);
-    let app = Router::new()
-        .route("/", get(index).post(create_post))
-        .route("/thread/:thread_id", get(get_thread).post(reply_post))
-        .nest_service("/static", ServeDir::new("static"))
-        .nest_service("/uploads", ServeDir::new("static/uploads"))
-        .nest_service("/thumbs", ServeDir::new("static/thumbs"))
-        .with_state(state);
-    println!("ChessBoard live at http://localhost:3000");
-    let listener = TcpListener::bind("127.0.0.1:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    for board in &config.boards {
+        sqlx::query!(
+            r#"
+            INSERT INTO boards
+                (slug, title, subtitle, max_file_size, allowed_extensions, default_name, reject_duplicate_files, require_spoiler)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (slug) DO UPDATE
+            SET title = EXCLUDED.title, subtitle = EXCLUDED.subtitle, max_file_size = EXCLUDED.max_file_size,
+                allowed_extensions = EXCLUDED.allowed_extensions, default_name = EXCLUDED.default_name,
+                reject_duplicate_files = EXCLUDED.reject_duplicate_files, require_spoiler = EXCLUDED.require_spoiler
+            "#,
+            board.slug,
+            board.title,
+            board.subtitle,
+            board.max_file_size,
+            &board.allowed_extensions,
+            board.default_name,
+            board.reject_duplicate_files,
+            board.require_spoiler
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+    let storage = storage::open(&config).unwrap_or_else(|e| {
+        eprintln!("Storage error: {}", e);
+        std::process::exit(1);
+    });
+    files::adopt_existing(&pool, &config, storage.as_ref()).await.expect("Failed to link existing uploads");
+    media::check_tools(&config).await;
+    // Only posting takes a body. A new thread's limit has to cover a full
+    // set of files on the most generous board plus the text fields; boards
+    // added while running need a restart to raise it. Each file is held to
+    // its board's own limit while it streams in.
+    let max_file_size = sqlx::query_scalar!("SELECT MAX(max_file_size) FROM boards")
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .unwrap_or(0);
+    let thread_limit = max_file_size as usize * config.limits.max_files_per_post + TEXT_FIELDS_LIMIT;
+    let bind = config.server.bind;
+    let filters = Filters::compile(&config.filters).expect("validated by Config::load");
+    let state = Arc::new(AppState { db: pool.clone(), config, metrics, storage, filters });
+    tokio::spawn(files::sweep_periodically(state.clone()));
+    let app = app(state, thread_limit);
+    tracing::info!(%bind, "ChessBoard live at http://{}", bind);
+    let listener = TcpListener::bind(bind).await.unwrap();
+    // Connection addresses identify posters for bans.
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal()).await.unwrap();
+    pool.close().await;
+    tracing::info!("database pool closed, exiting");
+}
+/// Every route, with its layers, over `state`. `thread_limit` caps the
+/// body of a new thread.
+fn app(state: SharedState, thread_limit: usize) -> Router {
+    let config = &state.config;
+    let mut app = Router::new()
+        .route("/", get(front_page))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .route("/:board", get(board_redirect))
+        .route("/:board/", get(index).post(create_post.layer(DefaultBodyLimit::max(thread_limit))))
+        .route("/:board/thread/:thread_id", get(get_thread).post(reply_post.layer(DefaultBodyLimit::max(TEXT_FIELDS_LIMIT))))
+        .route("/:board/file/:post_no/:position", get(download_file))
+        .route("/uploads/*name", get(serve_upload))
+        .route("/thumbs/*name", get(serve_thumb));
+    // The file server below would hand out whatever sits in the upload
+    // directories inside it, shards included, as its extension suggests and
+    // without `nosniff`. Send those paths through the stored-file handlers
+    // instead, which only serve flat names.
+    if let Some(subpath) = static_subpath(&config.paths.static_dir, &config.paths.uploads) {
+        app = app.route(&format!("/static/{}/*name", subpath), get(serve_upload));
+    }
+    if let Some(subpath) = static_subpath(&config.paths.static_dir, &config.paths.thumbs) {
+        app = app.route(&format!("/static/{}/*name", subpath), get(serve_thumb));
+    }
+    if config.features.text_search {
+        app = app.route("/search", get(search));
+    }
+    if config.features.position_search {
+        app = app.route("/search/position", get(search_position));
+    }
+    if state.metrics.is_some() {
+        app = app.route("/metrics", get(monitoring::render));
+    }
+    app.fallback(error::not_found)
+        .nest_service("/static", ServeDir::new(&config.paths.static_dir))
+        .route_layer(axum::middleware::from_fn(monitoring::track))
+        .layer(axum::middleware::from_fn(error::negotiate))
+        .layer(logging::trace_layer())
+        .with_state(state)
+}
+/// Moderation commands, run against the configured database and storage
+/// instead of starting the server.
+async fn run_command(command: Command, config: &Config) -> Result<(), String> {
+    let database_url = config.database.url.as_deref().expect("validated by Config::load");
+    let pool = PgPool::connect(database_url).await.map_err(|e| format!("Database error: {}", e))?;
+    match command {
+        Command::BanImage { images, reason } => {
+            let storage = storage::open(config).map_err(|e| format!("Storage error: {}", e))?;
+            for image in images {
+                let bytes = if std::path::Path::new(&image).is_file() {
+                    std::fs::read(&image).map_err(|e| format!("{}: {}", image, e))?
+                } else if storage::valid_name(&image) {
+                    storage.get(Area::Uploads, &image).await.map_err(|e| format!("{}: {}", image, e))?
+                } else {
+                    return Err(format!("{}: no such file or upload", image));
+                };
+                let hash = image::guess_format(&bytes)
+                    .ok()
+                    .and_then(|format| phash::of_image(&bytes, format))
+                    .ok_or_else(|| format!("{}: not an image that can be read", image))?;
+                sqlx::query!(
+                    r#"
+                    INSERT INTO banned_images (phash, reason) VALUES ($1, $2)
+                    ON CONFLICT (phash) DO UPDATE SET reason = EXCLUDED.reason
+                    "#,
+                    hash,
+                    reason
+                )
+                .execute(&pool)
+                .await
+                .map_err(|e| format!("Database error: {}", e))?;
+                println!("banned {} {}", phash::to_hex(hash), image);
+            }
+        }
+        Command::UnbanImage { hashes } => {
+            for text in hashes {
+                let hash = phash::from_hex(&text).ok_or_else(|| format!("{}: not a hash (16 hex digits)", text))?;
+                let lifted = sqlx::query!("DELETE FROM banned_images WHERE phash = $1", hash)
+                    .execute(&pool)
+                    .await
+                    .map_err(|e| format!("Database error: {}", e))?
+                    .rows_affected();
+                if lifted == 0 {
+                    println!("not banned {}", phash::to_hex(hash));
+                } else {
+                    println!("unbanned {}", phash::to_hex(hash));
+                }
+            }
+        }
+        Command::Unban { ips } => {
+            for ip in ips {
+                let lifted = sqlx::query!("DELETE FROM bans WHERE ip = $1", ip.to_string())
+                    .execute(&pool)
+                    .await
+                    .map_err(|e| format!("Database error: {}", e))?
+                    .rows_affected();
+                if lifted == 0 {
+                    println!("not banned {}", ip);
+                } else {
+                    println!("unbanned {}", ip);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+/// Where `dir` appears under `/static` if it lies inside `static_dir`, as a
+/// URL path such as `uploads` or `media/uploads`.
+fn static_subpath(static_dir: &std::path::Path, dir: &std::path::Path) -> Option<String> {
+    let canonical = |path: &std::path::Path| std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let relative = canonical(dir).strip_prefix(canonical(static_dir)).ok()?.to_path_buf();
+    let parts: Vec<&str> = relative.components().map(|part| part.as_os_str().to_str()).collect::<Option<_>>()?;
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+async fn serve_upload(state: axum::extract::State<SharedState>, Path(name): Path<String>, request: Request) -> AppResult<Response> {
+    serve_stored(&state, Area::Uploads, &name, request).await
+}
+async fn serve_thumb(state: axum::extract::State<SharedState>, Path(name): Path<String>, request: Request) -> AppResult<Response> {
+    serve_stored(&state, Area::Thumbs, &name, request).await
+}
+/// A stored file from whichever backend is configured, with the type its
+/// extension implies; `nosniff` stops browsers from guessing another one,
+/// such as HTML, from the content.
+async fn serve_stored(state: &AppState, area: Area, name: &str, request: Request) -> AppResult<Response> {
+    let not_found = || AppError::NotFound(format!("No such file: {}", name));
+    if !storage::valid_name(name) {
+        return Err(not_found());
+    }
+    let mut response = match state.storage.serve(area, name, request).await {
+        Ok(response) => response,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(not_found()),
+        Err(e) => return Err(e.into()),
+    };
+    response.headers_mut().insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    Ok(response)
+}
+
+/// Resolves on Ctrl-C or SIGTERM. The server then stops accepting
+/// connections and lets in-flight requests finish before `serve` returns.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("failed to listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("shutdown requested, draining in-flight requests");
 }
 async fn reset_database(database_url: &str) {
     let url = Url::parse(database_url).expect("Invalid DATABASE_URL");
@@ -67,172 +410,329 @@ async fn reset_database(database_url: &str) {
     sqlx::migrate!().run(&pool).await.expect("Failed to run migrations");
 }
 async fn seed_database(pool: &PgPool) {
-    let welcome = Post {
-        id: 0, // placeholder
-        thread_id: 1,
-        bump_timestamp: Utc::now().timestamp(),
-        name: "Anonymous".to_string(),
-        subject: Some("Welcome to /chess/".to_string()),
-        message: Some("First post! Let's discuss chess.\n>>greentext works".to_string()),
-        filename: None,
-        thumbname: None,
-        time: Utc::now().format("%Y-%m-%d %H:%M").to_string(),
-    };
-    let id = sqlx::query!(
+    let board_id = sqlx::query_scalar!("SELECT id FROM boards WHERE slug = 'chess'")
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    let mut tx = pool.begin().await.unwrap();
+    let post_no = create_thread(&mut tx, board_id).await.unwrap();
+    sqlx::query!(
         r#"
-        INSERT INTO posts (thread_id, bump_timestamp, name, subject, message, filename, thumbname, time)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-        RETURNING id
+        INSERT INTO posts (board_id, post_no, thread_id, name, subject, message)
+        VALUES ($1, $2, $2, 'Anonymous', 'Welcome to /chess/', $3)
         "#,
-        welcome.thread_id as i64,
-        welcome.bump_timestamp as i64,
-        welcome.name,
-        welcome.subject,
-        welcome.message,
-        welcome.filename,
-        welcome.thumbname,
-        welcome.time
+        board_id,
+        post_no,
+        "First post! Let's discuss chess.\n>>greentext works"
     )
-    .fetch_one(pool)
+    .execute(&mut *tx)
     .await
-    .unwrap()
-    .id;
-    sqlx::query!(
+    .unwrap();
+    tx.commit().await.unwrap();
+}
+/// Hands out the board's next post number. The row lock this takes is held
+/// until the transaction ends, so numbers stay unique and in posting order.
+async fn next_post_no(conn: &mut PgConnection, board_id: i32) -> AppResult<i64> {
+    let post_no = sqlx::query_scalar!(
         r#"
-        UPDATE posts
-        SET thread_id = $1
-        WHERE id = $2
+        UPDATE boards SET next_post_no = next_post_no + 1 WHERE id = $1 RETURNING next_post_no - 1 AS "post_no!"
         "#,
-        id as i64,
-        id as i64
+        board_id
     )
-    .execute(pool)
-    .await
-    .unwrap();
+    .fetch_one(conn)
+    .await?;
+    Ok(post_no)
+}
+/// Opens a thread under the next post number, which its OP must then be
+/// inserted with before the transaction commits.
+async fn create_thread(conn: &mut PgConnection, board_id: i32) -> AppResult<i64> {
+    let post_no = next_post_no(conn, board_id).await?;
+    sqlx::query!("INSERT INTO threads (board_id, post_no) VALUES ($1, $2)", board_id, post_no)
+        .execute(conn)
+        .await?;
+    Ok(post_no)
+}
+async fn load_board(pool: &PgPool, slug: &str) -> AppResult<Board> {
+    sqlx::query_as!(
+        Board,
+        r#"
+        SELECT id, slug, title, subtitle, max_file_size, allowed_extensions, default_name, reject_duplicate_files,
+               require_spoiler
+        FROM boards
+        WHERE slug = $1
+        "#,
+        slug
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Board /{}/ does not exist", slug)))
+}
+async fn front_page(state: axum::extract::State<SharedState>) -> AppResult<Html<String>> {
+    let boards: Vec<BoardListing> = sqlx::query_as!(
+        BoardListing,
+        r#"
+        SELECT b.slug, b.title, b.subtitle,
+               COUNT(p.id) FILTER (WHERE p.post_no = p.thread_id) AS "threads!",
+               COUNT(p.id) AS "posts!"
+        FROM boards b
+        LEFT JOIN posts p ON p.board_id = b.id
+        GROUP BY b.id
+        ORDER BY b.slug
+        "#
+    )
+    .fetch_all(&state.db)
+    .await?;
+    let mut html = page_header(&state.config.site.title, "Pick a board");
+    html.push_str(&search_forms(&state.config.features, None));
+    html.push_str("<table class=\"board-list\"><thead><tr><th>Board</th><th>Description</th><th>Threads</th><th>Posts</th></tr></thead><tbody>");
+    for board in &boards {
+        html.push_str(&format!(
+            "<tr><td><a href=\"/{}/\">/{}/ - {}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            board.slug,
+            board.slug,
+            escape(&board.title),
+            escape(&board.subtitle),
+            board.threads,
+            board.posts
+        ));
+    }
+    html.push_str("</tbody></table></body></html>");
+    Ok(Html(html))
+}
+async fn board_redirect(Path(slug): Path<String>) -> Redirect {
+    Redirect::permanent(&format!("/{}/", encode_query(&slug)))
 }
 async fn index(
     Query(query): Query<HashMap<String, String>>,
     state: axum::extract::State<SharedState>,
-) -> Html<String> {
+    Path(slug): Path<String>,
+    viewer: Viewer,
+) -> AppResult<Html<String>> {
+    let board = load_board(&state.db, &slug).await?;
     let page_str = query.get("page").cloned().unwrap_or_else(|| "1".to_string());
     let page: u64 = page_str.parse().unwrap_or(1).max(1);
-    const PER_PAGE: i64 = 15;
+    let per_page = state.config.limits.per_page;
     let total_threads: i64 = sqlx::query_scalar!(
         r#"
-        SELECT COUNT(*) FROM posts WHERE id = thread_id
-        "#
+        SELECT COUNT(*) FROM threads WHERE board_id = $1
+        "#,
+        board.id
     )
-    .fetch_one(&**state)
-    .await
-    .unwrap()
+    .fetch_one(&state.db)
+    .await?
     .unwrap_or(0);
-    let total_pages = if total_threads == 0 { 1 } else { ((total_threads as f64 / PER_PAGE as f64).ceil()) as u64 };
+    let total_pages = if total_threads == 0 { 1 } else { ((total_threads as f64 / per_page as f64).ceil()) as u64 };
     let page = page.min(total_pages);
-    let offset = ((page - 1) as i64) * PER_PAGE;
-    let ops: Vec<Post> = sqlx::query_as!(
-        Post,
-        r#"
-        SELECT id, thread_id, bump_timestamp, name, subject, message, filename, thumbname, time
-        FROM posts
-        WHERE id = thread_id
-        ORDER BY bump_timestamp DESC
-        LIMIT $1 OFFSET $2
-        "#,
-        PER_PAGE,
-        offset
-    )
-    .fetch_all(&**state)
-    .await
-    .unwrap();
-    let mut threads: HashMap<i64, Vec<Post>> = HashMap::new();
-    for op in &ops {
-        let thread_posts: Vec<Post> = sqlx::query_as!(
-            Post,
-            r#"
-            SELECT id, thread_id, bump_timestamp, name, subject, message, filename, thumbname, time
-            FROM posts
-            WHERE thread_id = $1
-            ORDER BY id
-            "#,
-            op.thread_id
-        )
-        .fetch_all(&**state)
-        .await
-        .unwrap();
-        threads.insert(op.thread_id, thread_posts);
-    }
-    let mut html = base_header("/", false);
+    let offset = ((page - 1) as i64) * per_page;
+    let threads = load_thread_previews(&state.db, board.id, per_page, offset, state.config.limits.preview_replies).await?;
+    let action = format!("/{}/", board.slug);
+    let mut html = base_header(&state.config, &board, &action, false);
     if let Some(error) = query.get("error") {
-        html.push_str(&format!("<div class=\"banner\" style=\"background-color: #E04000; color: white;\">Error: {} <a href=\"/\" style=\"color: white;\">Try again</a></div><hr>", escape(error)));
+        html.push_str(&format!("<div class=\"banner\" style=\"background-color: #E04000; color: white;\">Error: {} <a href=\"{}\" style=\"color: white;\">Try again</a></div><hr>", escape(error), action));
     }
-    for op in ops {
-        let thread_posts = threads.get(&op.thread_id).unwrap().clone();
+    for thread in threads {
         html.push_str("<div class=\"thread\">");
-        let replies = &thread_posts[1..];
-        render_post(&mut html, &thread_posts[0], true, Some(replies.len()));
-        let displayed_replies: &[Post];
-        if replies.len() > 3 {
-            displayed_replies = &replies[replies.len() - 3..];
-        } else {
-            displayed_replies = replies;
+        render_post(&mut html, &viewer, &board.slug, &thread.op, true, Some(thread.reply_count as usize));
+        let omitted = thread.reply_count - thread.replies.len() as i64;
+        if omitted > 0 {
+            let shown_images = std::iter::once(&thread.op)
+                .chain(&thread.replies)
+                .map(|post| post.files.len())
+                .sum::<usize>() as i64;
+            html.push_str(&format!(
+                "<span class=\"omitted\">{} repl{} and {} image{} omitted. <a href=\"/{}/thread/{}\">Click here</a> to view.</span>",
+                omitted,
+                if omitted == 1 { "y" } else { "ies" },
+                thread.image_count - shown_images,
+                if thread.image_count - shown_images == 1 { "" } else { "s" },
+                board.slug,
+                thread.op.thread_id
+            ));
         }
-        for reply in displayed_replies {
-            render_post(&mut html, reply, false, None);
+        for reply in &thread.replies {
+            render_post(&mut html, &viewer, &board.slug, reply, false, None);
             html.push_str("<br>");
         }
         html.push_str("</div><hr>");
     }
-    html.push_str(&render_pagination(page, total_pages));
+    html.push_str(&render_pagination(&format!("{}?", action), page, total_pages));
     html.push_str("</body></html>");
-    Html(html)
+    Ok(Html(html))
+}
+/// One page of threads in bump order, each with its OP, reply and image
+/// counts and only the last `preview` replies, fetched in a single query.
+async fn load_thread_previews(
+    pool: &PgPool,
+    board_id: i32,
+    limit: i64,
+    offset: i64,
+    preview: usize,
+) -> AppResult<Vec<ThreadPreview>> {
+    let rows = sqlx::query!(
+        r#"
+        WITH ops AS (
+            SELECT post_no AS thread_id, bumped_at
+            FROM threads
+            WHERE board_id = $1
+            ORDER BY bumped_at DESC, post_no DESC
+            LIMIT $2 OFFSET $3
+        ),
+        counts AS (
+            SELECT thread_id, COUNT(*) - 1 AS reply_count
+            FROM posts
+            WHERE board_id = $1 AND thread_id IN (SELECT thread_id FROM ops)
+            GROUP BY thread_id
+        ),
+        images AS (
+            SELECT p.thread_id, COUNT(*) AS image_count
+            FROM posts p
+            JOIN post_files pf ON pf.post_id = p.id
+            WHERE p.board_id = $1 AND p.thread_id IN (SELECT thread_id FROM ops)
+            GROUP BY p.thread_id
+        ),
+        shown AS (
+            SELECT thread_id AS post_no FROM ops
+            UNION ALL
+            SELECT last.post_no
+            FROM ops o
+            CROSS JOIN LATERAL (
+                SELECT post_no FROM posts
+                WHERE board_id = $1 AND thread_id = o.thread_id AND post_no <> o.thread_id
+                ORDER BY post_no DESC
+                LIMIT $4
+            ) last
+        )
+        SELECT p.id, p.board_id, p.post_no, p.thread_id, p.name, p.subject, p.message,
+               COALESCE(
+                   (SELECT json_agg(pf ORDER BY pf.position) FROM post_files pf WHERE pf.post_id = p.id),
+                   '[]'
+               ) AS "files!: Json<Vec<PostFile>>",
+               p.created_at, p.fen, p.pgn,
+               c.reply_count AS "reply_count!", COALESCE(i.image_count, 0) AS "image_count!"
+        FROM shown s
+        JOIN posts p ON p.board_id = $1 AND p.post_no = s.post_no
+        JOIN ops o ON o.thread_id = p.thread_id
+        JOIN counts c ON c.thread_id = p.thread_id
+        LEFT JOIN images i ON i.thread_id = p.thread_id
+        ORDER BY o.bumped_at DESC, o.thread_id DESC, p.post_no
+        "#,
+        board_id,
+        limit,
+        offset,
+        preview as i64
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut threads: Vec<ThreadPreview> = Vec::new();
+    for row in rows {
+        let post = Post {
+            id: row.id.into(),
+            board_id: row.board_id,
+            post_no: row.post_no,
+            thread_id: row.thread_id,
+            name: row.name,
+            subject: row.subject,
+            message: row.message,
+            files: row.files,
+            created_at: row.created_at,
+            fen: row.fen,
+            pgn: row.pgn,
+        };
+        // Rows arrive OP first, so an OP always opens a new preview.
+        if post.post_no == post.thread_id {
+            threads.push(ThreadPreview {
+                op: post,
+                replies: Vec::new(),
+                reply_count: row.reply_count,
+                image_count: row.image_count,
+            });
+        } else if let Some(thread) = threads.last_mut() {
+            thread.replies.push(post);
+        }
+    }
+    Ok(threads)
 }
 async fn get_thread(
     Query(query): Query<HashMap<String, String>>,
     state: axum::extract::State<SharedState>,
-    Path(thread_id): Path<i64>,
-) -> Html<String> {
+    Path((slug, thread_id)): Path<(String, i64)>,
+    viewer: Viewer,
+) -> AppResult<Html<String>> {
+    let board = load_board(&state.db, &slug).await?;
     let thread_posts: Vec<Post> = sqlx::query_as!(
         Post,
         r#"
-        SELECT id, thread_id, bump_timestamp, name, subject, message, filename, thumbname, time
-        FROM posts
-        WHERE thread_id = $1
-        ORDER BY id
+        SELECT p.id, p.board_id, p.post_no, p.thread_id, p.name, p.subject, p.message,
+               COALESCE(
+                   (SELECT json_agg(pf ORDER BY pf.position) FROM post_files pf WHERE pf.post_id = p.id),
+                   '[]'
+               ) AS "files!: Json<Vec<PostFile>>",
+               p.created_at, p.fen, p.pgn
+        FROM posts p
+        WHERE p.board_id = $1 AND p.thread_id = $2
+        ORDER BY p.post_no
         "#,
+        board.id,
         thread_id
     )
-    .fetch_all(&**state)
-    .await
-    .unwrap();
-    if thread_posts.is_empty() || thread_posts[0].id != thread_id {
-        let html = "<html><body>Thread not found. <a href=\"/\">Return to board</a></body></html>".to_string();
-        return Html(html);
+    .fetch_all(&state.db)
+    .await?;
+    if thread_posts.is_empty() || thread_posts[0].post_no != thread_id {
+        return Err(AppError::NotFound(format!("Thread /{}/{} does not exist", board.slug, thread_id)));
     }
-    let action = format!("/thread/{}", thread_id);
-    let mut html = base_header(&action, true);
+    let action = format!("/{}/thread/{}", board.slug, thread_id);
+    let mut html = base_header(&state.config, &board, &action, true);
     if let Some(error) = query.get("error") {
-        html.push_str(&format!("<div class=\"banner\" style=\"background-color: #E04000; color: white;\">Error: {} <a href=\"/thread/{}\" style=\"color: white;\">Try again</a></div><hr>", escape(error), thread_id));
+        html.push_str(&format!("<div class=\"banner\" style=\"background-color: #E04000; color: white;\">Error: {} <a href=\"{}\" style=\"color: white;\">Try again</a></div><hr>", escape(error), action));
     }
     html.push_str("<div class=\"thread\">");
-    render_post(&mut html, &thread_posts[0], true, Some(thread_posts.len() - 1));
+    render_post(&mut html, &viewer, &board.slug, &thread_posts[0], true, Some(thread_posts.len() - 1));
     for post in &thread_posts[1..] {
-        render_post(&mut html, post, false, None);
+        render_post(&mut html, &viewer, &board.slug, post, false, None);
         html.push_str("<br>");
     }
     html.push_str("</div><hr></body></html>");
-    Html(html)
+    Ok(Html(html))
 }
 async fn create_post(
     state: axum::extract::State<SharedState>,
+    Path(slug): Path<String>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     mut multipart: Multipart,
-) -> Redirect {
-    let mut name = "Anonymous".to_string();
+) -> AppResult<Redirect> {
+    let started = Instant::now();
+    let board = load_board(&state.db, &slug).await?;
+    let board_url = format!("/{}/", board.slug);
+    let ip = bans::client_ip(&state.config.server, peer, &headers);
+    if let Some(ban) = bans::find(&state.db, ip).await? {
+        return Ok(reject_post(&board, &board_url, "banned", &ban.notice()));
+    }
+    let mut name = board.default_name.clone();
     let mut subject: Option<String> = None;
     let mut message: Option<String> = None;
-    let mut filename: Option<String> = None;
-    let mut thumbname: Option<String> = None;
+    let mut fen: Option<String> = None;
+    let mut pgn: Option<String> = None;
+    // Spool file, detected format and the name the client gave, in upload order.
+    let mut uploads: Vec<(files::Spooled, Format, String)> = Vec::new();
+    let mut file_size = 0;
+    let mut too_many_files = false;
+    let mut spoiler = board.require_spoiler;
     let mut invalid_file = false;
-    while let Some(field) = multipart.next_field().await.unwrap() {
+    let mut file_too_large = false;
+    let mut mismatched_file: Option<String> = None;
+    let mut oversized_image: Option<(u32, u32)> = None;
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            // Past the route's body limit; only files can take it there.
+            Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                file_too_large = true;
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        };
         let field_name = field.name().unwrap_or("").to_string();
         match field_name.as_str() {
             "name" => {
@@ -255,95 +755,233 @@ async fn create_post(
                     message = Some(text);
                 }
             }
+            "fen" => {
+                let text = field.text().await.unwrap_or_default();
+                let trimmed = text.trim();
+                if !trimmed.is_empty() {
+                    fen = Some(trimmed.to_string());
+                }
+            }
+            "pgn" => {
+                let text = field.text().await.unwrap_or_default();
+                let trimmed = text.trim();
+                if !trimmed.is_empty() {
+                    pgn = Some(trimmed.to_string());
+                }
+            }
+            "spoiler" => spoiler = true,
             "file" => {
                 if let Some(original_name) = field.file_name() {
                     if original_name.is_empty() {
                         continue;
                     }
+                    if uploads.len() == state.config.limits.max_files_per_post {
+                        files::discard(&mut field).await;
+                        too_many_files = true;
+                        continue;
+                    }
                     let ext = std::path::Path::new(original_name)
                         .extension()
                         .and_then(|s| s.to_str())
                         .unwrap_or("")
                         .to_lowercase();
-                    let allowed_exts = vec!["jpg", "jpeg", "png", "gif", "webp"];
-                    if !allowed_exts.contains(&ext.as_str()) {
-                        let _ = field.bytes().await.unwrap_or_default(); // consume bytes
+                    let original_name = files::sanitize_name(original_name, &ext);
+                    if !board.allowed_extensions.contains(&ext) {
+                        files::discard(&mut field).await;
                         invalid_file = true;
                         continue;
                     }
-                    let uuid = Uuid::new_v4().to_string();
-                    let new_name = format!("{}.{}", uuid, ext);
-                    let thumb_name = format!("{}_thumb.jpg", uuid);
-                    let bytes = field.bytes().await.unwrap();
-                    if bytes.is_empty() {
+                    let Some(spooled) = files::spool(&mut field, board.max_file_size as usize).await? else {
+                        file_too_large = true;
+                        continue;
+                    };
+                    if spooled.size == 0 {
                         continue;
                     }
-                    let upload_path = format!("static/uploads/{}", new_name);
-                    let thumb_path = format!("static/thumbs/{}", thumb_name);
-                    std::fs::write(&upload_path, &bytes).unwrap();
-                    if let Ok(img) = image::load_from_memory(&bytes) {
-                        let thumb = img.resize(150, 150, FilterType::Lanczos3);
-                        thumb.save_with_format(&thumb_path, ImageFormat::Jpeg).unwrap();
-                        thumbname = Some(thumb_name);
-                    } else {
-                        thumbname = None;
+                    file_size += spooled.size;
+                    // The bytes decide the format; the extension only has to agree.
+                    let Some(format) = Format::detect(&spooled.head).filter(|format| format.has_extension(&ext)) else {
+                        mismatched_file = Some(ext);
+                        continue;
+                    };
+                    let Format::Image(image_format) = format else {
+                        uploads.push((spooled, format, original_name));
+                        continue;
+                    };
+                    // Checked from the header, before anything decodes the pixels.
+                    match spooled.dimensions(image_format) {
+                        Some((width, height)) if u64::from(width) * u64::from(height) > state.config.limits.max_image_pixels => {
+                            oversized_image = Some((width, height));
+                        }
+                        Some(_) => uploads.push((spooled, format, original_name)),
+                        None => mismatched_file = Some(ext),
                     }
-                    filename = Some(new_name);
                 }
             }
             _ => {}
         }
     }
+    if too_many_files {
+        let error = format!("Too many files. Maximum: {} per post", state.config.limits.max_files_per_post);
+        return Ok(reject_post(&board, &board_url, "file_count", &error));
+    }
     if invalid_file {
-        return Redirect::to("/?error=Invalid%20file%20type.%20Allowed:%20jpg,%20jpeg,%20png,%20gif,%20webp");
+        let error = format!("Invalid file type. Allowed: {}", board.allowed_extensions.join(", "));
+        return Ok(reject_post(&board, &board_url, "file_type", &error));
+    }
+    if file_too_large {
+        let error = format!("File too large. Maximum size: {} KB", board.max_file_size / 1024);
+        return Ok(reject_post(&board, &board_url, "file_size", &error));
+    }
+    if let Some(ext) = mismatched_file {
+        let error = format!("File contents do not match the .{} extension", ext);
+        return Ok(reject_post(&board, &board_url, "file_mismatch", &error));
+    }
+    if let Some((width, height)) = oversized_image {
+        let error = format!(
+            "Image is {}×{} pixels. Maximum: {} megapixels",
+            width,
+            height,
+            state.config.limits.max_image_pixels as f64 / 1e6
+        );
+        return Ok(reject_post(&board, &board_url, "image_dimensions", &error));
     }
     // Enforce required fields: subject and message must be present and non-empty
     if subject.is_none() || message.is_none() {
-        return Redirect::to("/?error=Missing%20subject%20or%20comment");
+        return Ok(reject_post(&board, &board_url, "missing_field", "Missing subject or comment"));
+    }
+    let fields = std::iter::once(&mut name).chain(subject.as_mut()).chain(message.as_mut());
+    if let Some(rejected) = screen_text(&state, &board, ip, &board_url, fields).await? {
+        return Ok(rejected);
+    }
+    // Files are only written once the post is known to be accepted.
+    let mut stored_files = Vec::with_capacity(uploads.len());
+    for (spooled, format, original_name) in uploads {
+        let upload = match format {
+            Format::Image(image_format) => {
+                // Within the board's size limit, so whole in memory is fine.
+                let original = tokio::fs::read(spooled.path()).await?;
+                // Posters are anonymous; what the camera recorded must not be published.
+                let Some(bytes) = metadata::strip(&original, image_format) else {
+                    return Ok(reject_post(&board, &board_url, "file_unreadable", "The image could not be read"));
+                };
+                let phash = phash::of_image(&bytes, image_format);
+                if let Some(hash) = phash {
+                    let moderation = &state.config.moderation;
+                    if let Some(banned) = phash::find_ban(&state.db, hash, moderation.image_ban_distance).await? {
+                        tracing::info!(
+                            board = %board.slug,
+                            phash = %phash::to_hex(hash),
+                            banned = %phash::to_hex(banned),
+                            "banned image posted"
+                        );
+                        return Ok(reject_post(&board, &board_url, "banned_image", &moderation.banned_image_message));
+                    }
+                }
+                files::Upload::Image { bytes, original, format: image_format, phash }
+            }
+            Format::Media(container) => files::Upload::Media { spooled, container },
+        };
+        let sha256 = match &upload {
+            files::Upload::Image { bytes, .. } => files::sha256(bytes),
+            files::Upload::Media { spooled, .. } => spooled.sha256.clone(),
+        };
+        if board.reject_duplicate_files {
+            if let Some((thread_id, post_no)) = files::find_on_board(&state.db, board.id, &sha256).await? {
+                let error = format!("This file was already posted in /{}/thread/{} (No.{})", board.slug, thread_id, post_no);
+                return Ok(reject_post(&board, &board_url, "duplicate_file", &error));
+            }
+        }
+        let stored = files::store(&state, &board.slug, &upload, &sha256).await?;
+        stored_files.push((stored, original_name));
     }
-    let time = Utc::now().format("%Y-%m-%d %H:%M").to_string();
-    let bump_timestamp = Utc::now().timestamp();
-    let id = sqlx::query!(
+    // The thread, its OP, the OP's files and positions commit together or not at all.
+    let mut tx = state.db.begin().await?;
+    let post_no = create_thread(&mut tx, board.id).await?;
+    let post_id = sqlx::query_scalar!(
         r#"
-        INSERT INTO posts (thread_id, bump_timestamp, name, subject, message, filename, thumbname, time)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        INSERT INTO posts (board_id, post_no, thread_id, name, subject, message, fen, pgn)
+        VALUES ($1, $2, $2, $3, $4, $5, $6, $7)
         RETURNING id
         "#,
-        bump_timestamp as i64, // temp thread_id
-        bump_timestamp as i64,
+        board.id,
+        post_no,
         name,
         subject,
         message,
-        filename,
-        thumbname,
-        time
-    )
-    .fetch_one(&**state)
-    .await
-    .unwrap()
-    .id;
-    sqlx::query!(
-        r#"
-        UPDATE posts
-        SET thread_id = $1
-        WHERE id = $2
-        "#,
-        id as i64,
-        id as i64
+        fen,
+        pgn
     )
-    .execute(&**state)
-    .await
-    .unwrap();
-    Redirect::to("/")
+    .fetch_one(&mut *tx)
+    .await?;
+    for (position, (file, original_name)) in stored_files.iter().enumerate() {
+        sqlx::query!(
+            r#"
+            INSERT INTO post_files
+                (post_id, position, file_sha256, filename, thumbname, original_name, size, kind, width, height, duration_ms, spoiler)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            "#,
+            post_id,
+            position as i16,
+            file.sha256,
+            file.filename,
+            file.thumbname,
+            original_name,
+            file.size,
+            file.kind,
+            file.width,
+            file.height,
+            file.duration_ms,
+            spoiler
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    index_positions(&mut tx, post_id, fen.as_deref(), pgn.as_deref()).await?;
+    tx.commit().await?;
+    tracing::info!(
+        board = %board.slug,
+        thread_id = post_no,
+        post_no,
+        files = stored_files.len(),
+        file_size,
+        latency_ms = started.elapsed().as_millis() as u64,
+        "thread created"
+    );
+    monitoring::post_created(&board.slug, true);
+    Ok(Redirect::to(&board_url))
 }
 async fn reply_post(
     state: axum::extract::State<SharedState>,
-    Path(thread_id): Path<i64>,
+    Path((slug, thread_id)): Path<(String, i64)>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     mut multipart: Multipart,
-) -> Redirect {
-    let mut name = "Anonymous".to_string();
+) -> AppResult<Redirect> {
+    let started = Instant::now();
+    let board = load_board(&state.db, &slug).await?;
+    let thread_exists = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM threads WHERE board_id = $1 AND post_no = $2) AS "exists!"
+        "#,
+        board.id,
+        thread_id
+    )
+    .fetch_one(&state.db)
+    .await?;
+    if !thread_exists {
+        return Err(AppError::NotFound(format!("Thread /{}/{} does not exist", board.slug, thread_id)));
+    }
+    let thread_url = format!("/{}/thread/{}", board.slug, thread_id);
+    let ip = bans::client_ip(&state.config.server, peer, &headers);
+    if let Some(ban) = bans::find(&state.db, ip).await? {
+        return Ok(reject_post(&board, &thread_url, "banned", &ban.notice()));
+    }
+    let mut name = board.default_name.clone();
     let mut message: Option<String> = None;
-    while let Some(field) = multipart.next_field().await.unwrap() {
+    let mut fen: Option<String> = None;
+    let mut pgn: Option<String> = None;
+    while let Some(field) = multipart.next_field().await? {
         let field_name = field.name().unwrap_or("").to_string();
         match field_name.as_str() {
             "name" => {
@@ -359,101 +997,614 @@ async fn reply_post(
                     message = Some(text);
                 }
             }
+            "fen" => {
+                let text = field.text().await.unwrap_or_default();
+                let trimmed = text.trim();
+                if !trimmed.is_empty() {
+                    fen = Some(trimmed.to_string());
+                }
+            }
+            "pgn" => {
+                let text = field.text().await.unwrap_or_default();
+                let trimmed = text.trim();
+                if !trimmed.is_empty() {
+                    pgn = Some(trimmed.to_string());
+                }
+            }
             _ => {}
         }
     }
     // Enforce required fields: only message must be non-empty
     if message.is_none() {
-        return Redirect::to(&format!("/thread/{}?error=Missing%20comment", thread_id));
+        return Ok(reject_post(&board, &thread_url, "missing_field", "Missing comment"));
     }
-    let time = Utc::now().format("%Y-%m-%d %H:%M").to_string();
-    sqlx::query!(
+    let fields = std::iter::once(&mut name).chain(message.as_mut());
+    if let Some(rejected) = screen_text(&state, &board, ip, &thread_url, fields).await? {
+        return Ok(rejected);
+    }
+    let mut tx = state.db.begin().await?;
+    let post_no = next_post_no(&mut tx, board.id).await?;
+    let post_id = sqlx::query_scalar!(
         r#"
-        INSERT INTO posts (thread_id, bump_timestamp, name, subject, message, filename, thumbname, time)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        INSERT INTO posts (board_id, post_no, thread_id, name, message, fen, pgn)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id
         "#,
-        thread_id as i64,
-        0i64,
+        board.id,
+        post_no,
+        thread_id,
         name,
-        None::<String>,
         message,
-        None::<String>,
-        None::<String>,
-        time
+        fen,
+        pgn
     )
-    .execute(&**state)
-    .await
-    .unwrap();
+    .fetch_one(&mut *tx)
+    .await?;
+    index_positions(&mut tx, post_id, fen.as_deref(), pgn.as_deref()).await?;
+    // now() is the transaction's start, the same instant as the reply's created_at.
     sqlx::query!(
+        "UPDATE threads SET bumped_at = now() WHERE board_id = $1 AND post_no = $2",
+        board.id,
+        thread_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    tracing::info!(
+        board = %board.slug,
+        thread_id,
+        post_no,
+        latency_ms = started.elapsed().as_millis() as u64,
+        "reply created"
+    );
+    monitoring::post_created(&board.slug, false);
+    Ok(Redirect::to(&thread_url))
+}
+/// A post's file as an attachment saved under the name it was uploaded
+/// with. The same content may have been posted under other names, so the
+/// name comes from the post rather than from the stored copy.
+async fn download_file(
+    state: axum::extract::State<SharedState>,
+    Path((slug, post_no, position)): Path<(String, i64, i16)>,
+) -> AppResult<Response> {
+    let board = load_board(&state.db, &slug).await?;
+    let not_found = || AppError::NotFound(format!("No file {} on /{}/ No.{}", position, board.slug, post_no));
+    let file = sqlx::query!(
         r#"
-        UPDATE posts
-        SET bump_timestamp = $1
-        WHERE id = $2
+        SELECT pf.filename, pf.original_name
+        FROM post_files pf
+        JOIN posts p ON p.id = pf.post_id
+        WHERE p.board_id = $1 AND p.post_no = $2 AND pf.position = $3
         "#,
-        Utc::now().timestamp() as i64,
-        thread_id as i64
+        board.id,
+        post_no,
+        position
     )
-    .execute(&**state)
-    .await
-    .unwrap();
-    Redirect::to(&format!("/thread/{}", thread_id))
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(not_found)?;
+    let bytes = match state.storage.get(Area::Uploads, &file.filename).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(not_found()),
+        Err(e) => return Err(e.into()),
+    };
+    let disposition = HeaderValue::from_str(&files::content_disposition(&file.original_name))
+        .unwrap_or_else(|_| HeaderValue::from_static("attachment"));
+    let headers = [
+        (header::CONTENT_TYPE, HeaderValue::from_static("application/octet-stream")),
+        (header::CONTENT_DISPOSITION, disposition),
+        (header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+    ];
+    Ok((headers, bytes).into_response())
 }
-fn base_header(action: &str, is_reply: bool) -> String {
-    let mut header = format!(
+/// Runs the word filters over a post's text fields, rewriting them in
+/// place, then holds the post to the link limit. Returns the redirect for a
+/// post turned away; a `ban` filter bans `ip` first.
+async fn screen_text(
+    state: &AppState,
+    board: &Board,
+    ip: IpAddr,
+    back: &str,
+    fields: impl Iterator<Item = &mut String>,
+) -> AppResult<Option<Redirect>> {
+    let mut links = 0;
+    for field in fields {
+        match state.filters.apply(&board.slug, field) {
+            Ok(filtered) => *field = filtered,
+            Err(Blocked::Rejected { message }) => return Ok(Some(reject_post(board, back, "filtered", &message))),
+            Err(Blocked::Banned { message, hours }) => {
+                bans::add(&state.db, ip, &message, hours).await?;
+                tracing::info!(board = %board.slug, %ip, hours, "poster banned by a filter");
+                return Ok(Some(reject_post(board, back, "filter_ban", &message)));
+            }
+        }
+        links += filters::count_links(field);
+    }
+    let max_links = state.config.moderation.max_links_per_post;
+    if links > max_links {
+        let error = format!("Too many links. Maximum: {} per post", max_links);
+        return Ok(Some(reject_post(board, back, "link_count", &error)));
+    }
+    Ok(None)
+}
+/// Sends the poster back to the form with `error` shown in the banner.
+fn reject_post(board: &Board, back: &str, reason: &'static str, error: &str) -> Redirect {
+    tracing::info!(board = %board.slug, reason, "post rejected");
+    monitoring::post_rejected(&board.slug, reason);
+    Redirect::to(&format!("{}?error={}", back, encode_query(error)))
+}
+async fn index_positions(conn: &mut PgConnection, post_id: i32, fen: Option<&str>, pgn: Option<&str>) -> AppResult<()> {
+    let hashes = chess::post_hashes(fen, pgn);
+    if hashes.is_empty() {
+        return Ok(());
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO post_positions (zobrist, post_id)
+        SELECT UNNEST($1::BIGINT[]), $2
+        "#,
+        &hashes,
+        post_id
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+// ts_headline marks matches with these private-use characters so the text
+// can be HTML-escaped first and the markers turned into <mark> afterwards.
+const HL_START: char = '\u{E000}';
+const HL_STOP: char = '\u{E001}';
+const HL_OPTIONS: &str = "StartSel=\u{E000}, StopSel=\u{E001}, MaxFragments=3, MaxWords=30, MinWords=10";
+async fn search(
+    Query(query): Query<HashMap<String, String>>,
+    state: axum::extract::State<SharedState>,
+    viewer: Viewer,
+) -> AppResult<Html<String>> {
+    let per_page = state.config.limits.per_page;
+    let q = query.get("q").map(|q| q.trim().to_string()).unwrap_or_default();
+    let board = query.get("board").map(|b| b.trim().to_string()).filter(|b| !b.is_empty());
+    // Post numbers are per board, so a thread means nothing without one.
+    let thread: Option<i64> = query.get("thread").and_then(|t| t.trim().parse().ok()).filter(|_| board.is_some());
+    let from = query
+        .get("from")
+        .and_then(|d| NaiveDate::parse_from_str(d.trim(), "%Y-%m-%d").ok());
+    let to = query
+        .get("to")
+        .and_then(|d| NaiveDate::parse_from_str(d.trim(), "%Y-%m-%d").ok());
+    let has_file = query.get("has_file").is_some_and(|v| v == "on" || v == "1");
+    let page: u64 = query.get("page").and_then(|p| p.parse().ok()).unwrap_or(1).max(1);
+
+    let slugs: Vec<String> = sqlx::query_scalar!("SELECT slug FROM boards ORDER BY slug")
+        .fetch_all(&state.db)
+        .await?;
+    let mut html = page_header(&state.config.site.title, "Search");
+    html.push_str(&render_search_form(&q, &slugs, board.as_deref(), thread, from, to, has_file));
+    if q.is_empty() {
+        html.push_str("</body></html>");
+        return Ok(Html(html));
+    }
+    // Dates are days in the reader's time zone; `to` includes the whole day.
+    let from_bound = from.and_then(|d| viewer.start_of(d));
+    let to_bound = to.and_then(|d| d.succ_opt()).and_then(|d| viewer.start_of(d));
+
+    let total: i64 = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM posts p
+        JOIN boards b ON b.id = p.board_id
+        WHERE p.search_vector @@ websearch_to_tsquery('english', $1)
+          AND ($2::TEXT IS NULL OR b.slug = $2)
+          AND ($3::BIGINT IS NULL OR p.thread_id = $3)
+          AND ($4::TIMESTAMPTZ IS NULL OR p.created_at >= $4)
+          AND ($5::TIMESTAMPTZ IS NULL OR p.created_at < $5)
+          AND (NOT $6 OR EXISTS (SELECT 1 FROM post_files pf WHERE pf.post_id = p.id))
+        "#,
+        q,
+        board,
+        thread,
+        from_bound,
+        to_bound,
+        has_file
+    )
+    .fetch_one(&state.db)
+    .await?;
+    let total_pages = if total == 0 { 1 } else { ((total as f64 / per_page as f64).ceil()) as u64 };
+    let page = page.min(total_pages);
+    let offset = ((page - 1) as i64) * per_page;
+    let hits: Vec<SearchHit> = sqlx::query_as!(
+        SearchHit,
+        r#"
+        SELECT b.slug AS board, p.post_no, p.thread_id, p.name, p.created_at,
+               first.thumbname AS "thumbname?", COALESCE(first.spoiler, false) AS "spoiler!",
+               ts_headline('english', coalesce(p.subject, ''), tsq, $7) AS "subject_hl!",
+               ts_headline('english', coalesce(p.message, ''), tsq, $7) AS "message_hl!"
+        FROM posts p
+        JOIN boards b ON b.id = p.board_id
+        LEFT JOIN LATERAL (
+            SELECT pf.thumbname, pf.spoiler FROM post_files pf WHERE pf.post_id = p.id ORDER BY pf.position LIMIT 1
+        ) first ON true,
+             websearch_to_tsquery('english', $1) AS tsq
+        WHERE p.search_vector @@ tsq
+          AND ($2::TEXT IS NULL OR b.slug = $2)
+          AND ($3::BIGINT IS NULL OR p.thread_id = $3)
+          AND ($4::TIMESTAMPTZ IS NULL OR p.created_at >= $4)
+          AND ($5::TIMESTAMPTZ IS NULL OR p.created_at < $5)
+          AND (NOT $6 OR EXISTS (SELECT 1 FROM post_files pf WHERE pf.post_id = p.id))
+        ORDER BY ts_rank(p.search_vector, tsq) DESC, p.id DESC
+        LIMIT $8 OFFSET $9
+        "#,
+        q,
+        board,
+        thread,
+        from_bound,
+        to_bound,
+        has_file,
+        HL_OPTIONS,
+        per_page,
+        offset
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    html.push_str(&format!("<div class=\"banner\">{} result(s) for {}</div>", total, escape(&q)));
+    for hit in &hits {
+        render_search_hit(&mut html, &viewer, hit);
+    }
+    html.push_str("<hr>");
+    let mut base = url::form_urlencoded::Serializer::new(String::new());
+    base.append_pair("q", &q);
+    if let Some(board) = &board {
+        base.append_pair("board", board);
+    }
+    if let Some(thread) = thread {
+        base.append_pair("thread", &thread.to_string());
+    }
+    if let Some(from) = from {
+        base.append_pair("from", &from.format("%Y-%m-%d").to_string());
+    }
+    if let Some(to) = to {
+        base.append_pair("to", &to.format("%Y-%m-%d").to_string());
+    }
+    if has_file {
+        base.append_pair("has_file", "1");
+    }
+    html.push_str(&render_pagination(&format!("/search?{}&", base.finish()), page, total_pages));
+    html.push_str("</body></html>");
+    Ok(Html(html))
+}
+fn render_search_form(
+    q: &str,
+    slugs: &[String],
+    board: Option<&str>,
+    thread: Option<i64>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    has_file: bool,
+) -> String {
+    let date = |d: Option<NaiveDate>| d.map(|d| d.format("%Y-%m-%d").to_string()).unwrap_or_default();
+    let mut options = r#"<option value="">All boards</option>"#.to_string();
+    for slug in slugs {
+        let selected = if board == Some(slug.as_str()) { " selected" } else { "" };
+        options.push_str(&format!(r#"<option value="{}"{}>/{}/</option>"#, slug, selected, slug));
+    }
+    format!(
+        r#"<form method="get" action="/search">
+<table class="post-table">
+<tbody>
+    <tr><th>Search</th><td><input type="text" name="q" size="35" maxlength="200" autocomplete="off" value="{}"></td></tr>
+    <tr><th>Board</th><td><select name="board">{}</select></td></tr>
+    <tr><th>Thread</th><td><input type="text" name="thread" size="10" autocomplete="off" value="{}"> On the board above</td></tr>
+    <tr><th>From</th><td><input type="date" name="from" value="{}"></td></tr>
+    <tr><th>To</th><td><input type="date" name="to" value="{}"></td></tr>
+    <tr><th>File</th><td><label><input type="checkbox" name="has_file" value="1"{}> Only posts with a file</label></td></tr>
+    <tr><th></th><td><input type="submit" value="Search" /> <a href="/">Return to the board list</a></td></tr>
+</tbody>
+</table>
+</form>
+<hr>"#,
+        escape(q),
+        options,
+        thread.map(|t| t.to_string()).unwrap_or_default(),
+        date(from),
+        date(to),
+        if has_file { " checked" } else { "" }
+    )
+}
+fn highlight(s: &str) -> String {
+    escape(s)
+        .replace(HL_START, "<mark>")
+        .replace(HL_STOP, "</mark>")
+}
+fn render_search_hit(html: &mut String, viewer: &Viewer, hit: &SearchHit) {
+    html.push_str("<div class=\"post reply search-hit\"><div class=\"intro\">");
+    if hit.spoiler {
+        html.push_str(&format!(r#"<img src="{}" class="post-image" alt="Spoiler">"#, SPOILER_IMAGE));
+    } else if let Some(thumb) = &hit.thumbname {
+        html.push_str(&format!(r#"<img src="/thumbs/{}" class="post-image">"#, thumb));
+    }
+    if !hit.subject_hl.is_empty() {
+        html.push_str(&format!("<span class=\"subject\">{}</span> ", highlight(&hit.subject_hl)));
+    }
+    html.push_str(&format!(
+        "<span class=\"name\">{}</span> {} <a href=\"/{}/thread/{}#{}\">/{}/ No.{}</a>",
+        escape(&hit.name),
+        viewer.time(hit.created_at),
+        hit.board,
+        hit.thread_id,
+        hit.post_no,
+        hit.board,
+        hit.post_no
+    ));
+    html.push_str(&format!("</div><div class=\"body\">{}</div></div>", highlight(&hit.message_hl)));
+}
+async fn search_position(
+    Query(query): Query<HashMap<String, String>>,
+    state: axum::extract::State<SharedState>,
+    viewer: Viewer,
+) -> AppResult<Html<String>> {
+    let fen = query.get("fen").map(|f| f.trim()).unwrap_or("");
+    let board = query.get("board").map(|b| b.trim()).filter(|b| !b.is_empty());
+    let pos = chess::parse_fen(fen).ok_or_else(|| AppError::Validation(format!("Not a valid position: {}", fen)))?;
+    let mut html = page_header(&state.config.site.title, "Position search");
+    let slugs: HashMap<i32, String> = sqlx::query!("SELECT id, slug FROM boards")
+        .fetch_all(&state.db)
+        .await?
+        .into_iter()
+        .map(|b| (b.id, b.slug))
+        .collect();
+    let posts: Vec<Post> = sqlx::query_as!(
+        Post,
+        r#"
+        SELECT p.id, p.board_id, p.post_no, p.thread_id, p.name, p.subject, p.message,
+               COALESCE(
+                   (SELECT json_agg(pf ORDER BY pf.position) FROM post_files pf WHERE pf.post_id = p.id),
+                   '[]'
+               ) AS "files!: Json<Vec<PostFile>>",
+               p.created_at, p.fen, p.pgn
+        FROM posts p
+        JOIN post_positions pp ON pp.post_id = p.id
+        JOIN boards b ON b.id = p.board_id
+        WHERE pp.zobrist = $1
+          AND ($2::TEXT IS NULL OR b.slug = $2)
+        ORDER BY b.slug, p.thread_id DESC, p.post_no
+        "#,
+        chess::position_hash(&pos),
+        board
+    )
+    .fetch_all(&state.db)
+    .await?;
+    let mut threads: Vec<(i32, i64)> = posts.iter().map(|p| (p.board_id, p.thread_id)).collect();
+    threads.dedup();
+    html.push_str(&format!(
+        "<div class=\"banner\">{} post(s) in {} thread(s) reached this position <a href=\"/\">Return to the board list</a></div>",
+        posts.len(),
+        threads.len()
+    ));
+    html.push_str(&format!("<div class=\"diagram\">{}</div><hr>", chess::diagram_html(&pos)));
+    for (board_id, thread_id) in threads {
+        let slug = &slugs[&board_id];
+        html.push_str(&format!("<div class=\"thread\"><a href=\"/{}/thread/{}\">/{}/ Thread No.{}</a>", slug, thread_id, slug, thread_id));
+        for post in posts.iter().filter(|p| p.board_id == board_id && p.thread_id == thread_id) {
+            render_post(&mut html, &viewer, slug, post, post.post_no == post.thread_id, None);
+        }
+        html.push_str("</div><hr>");
+    }
+    html.push_str("</body></html>");
+    Ok(Html(html))
+}
+fn page_header(title: &str, subtitle: &str) -> String {
+    format!(
         r#"<!DOCTYPE html>
 <html>
 <head>
     <meta charset="utf-8">
-    <title>/chess/ - Chess</title>
+    <title>{}</title>
     <link rel="stylesheet" href="/static/default.css">
+    <script src="/static/timezone.js" defer></script>
 </head>
 <body>
+<div class="boardlist">[ <a href="/">All boards</a> ]</div>
 <header>
-    <h1>/chess/ - Chess</h1>
-    <div class="subtitle">General chess discussion, puzzles, and diagrams</div>
+    <h1>{}</h1>
+    <div class="subtitle">{}</div>
 </header>
-<hr>"#
-    );
+<hr>"#,
+        escape(title),
+        escape(title),
+        escape(subtitle)
+    )
+}
+/// The quick text and position search boxes that are enabled; on a board
+/// page they are limited to that board.
+fn search_forms(features: &FeaturesConfig, board: Option<&str>) -> String {
+    let hidden = board
+        .map(|slug| format!(r#"<input type="hidden" name="board" value="{}">"#, slug))
+        .unwrap_or_default();
+    let mut forms = String::new();
+    if features.text_search {
+        forms.push_str(&format!(
+            r#"<form method="get" action="/search" class="search-form">{}Search <input type="text" name="q" size="35" maxlength="200" autocomplete="off" placeholder="words or &quot;a phrase&quot;"> <input type="submit" value="Search"></form>
+"#,
+            hidden
+        ));
+    }
+    if features.position_search {
+        forms.push_str(&format!(
+            r#"<form method="get" action="/search/position" class="search-form">{}Position <input type="text" name="fen" size="50" maxlength="100" autocomplete="off" placeholder="FEN"> <input type="submit" value="Search"></form>
+"#,
+            hidden
+        ));
+    }
+    forms.push_str("<hr>");
+    forms
+}
+fn base_header(config: &Config, board: &Board, action: &str, is_reply: bool) -> String {
+    let mut header = page_header(&format!("/{}/ - {}", board.slug, board.title), &board.subtitle);
     if is_reply {
-        header.push_str(r#"<div class="banner">Reply mode <a href="/">Return to the main board</a></div>"#);
+        header.push_str(&format!(r#"<div class="banner">Reply mode <a href="/{}/">Return to the main board</a></div>"#, board.slug));
     }
     header.push_str(&format!(
         r#"<form method="post" action="{}" enctype="multipart/form-data">
 <table class="post-table">
 <tbody>
-    <tr><th>Name</th><td><input type="text" name="name" size="25" maxlength="35" autocomplete="off" placeholder="Anonymous"></td></tr>
+    <tr><th>Name</th><td><input type="text" name="name" size="25" maxlength="35" autocomplete="off" placeholder="{}"></td></tr>
 "#,
-        action
+        action,
+        escape(&board.default_name)
     ));
     if !is_reply {
         header.push_str(r#"<tr><th>Subject</th><td><input type="text" name="subject" size="25" maxlength="100" autocomplete="off"></td></tr>"#);
     }
     header.push_str(r#"<tr><th>Comment</th><td><textarea name="message" rows="5" cols="35"></textarea></td></tr>"#);
+    header.push_str(r#"<tr><th>FEN</th><td><input type="text" name="fen" size="50" maxlength="100" autocomplete="off"></td></tr>"#);
+    header.push_str(r#"<tr><th>PGN</th><td><textarea name="pgn" rows="4" cols="35"></textarea></td></tr>"#);
     if !is_reply {
-        header.push_str(r#"<tr><th>File</th><td><input type="file" name="file" id="upload_file"></td></tr>"#);
+        let accept: Vec<String> = board.allowed_extensions.iter().map(|ext| format!(".{}", ext)).collect();
+        header.push_str(&format!(
+            r#"<tr><th>Files</th><td><input type="file" name="file" id="upload_file" accept="{}" multiple> <small>Up to {}, max {} KB each</small></td></tr>"#,
+            escape(&accept.join(",")),
+            config.limits.max_files_per_post,
+            board.max_file_size / 1024
+        ));
+        if board.require_spoiler {
+            header.push_str(r#"<tr><th>Spoiler</th><td><small>Files on this board are always spoilered</small></td></tr>"#);
+        } else {
+            header.push_str(r#"<tr><th>Spoiler</th><td><label><input type="checkbox" name="spoiler" value="1"> Hide the files behind a spoiler</label></td></tr>"#);
+        }
     }
     header.push_str(r#"<tr><th></th><td><input accesskey="s" type="submit" name="post" value="Post" /></td></tr>
 </tbody>
 </table>
 </form>
-<hr>"#);
+"#);
+    header.push_str(&search_forms(&config.features, Some(&board.slug)));
     header
 }
-fn render_post(html: &mut String, post: &Post, is_op: bool, reply_count: Option<usize>) {
-    html.push_str(&format!("<div id=\"{}\" class=\"post {}\">", post.id, if is_op { "op" } else { "reply" }));
-    html.push_str("<div class=\"intro\">");
-    if let (Some(file), Some(thumb)) = (&post.filename, &post.thumbname) {
-        if !file.is_empty() && !thumb.is_empty() {
-            html.push_str(&format!(
-                r#"<span class="file">
-    <a href="/uploads/{}" target="_blank">
-        <img src="/thumbs/{}" class="post-image">
-    </a>
-</span>"#,
-                file, thumb
-            ));
+/// Stands in for the thumbnail of a spoilered file.
+const SPOILER_IMAGE: &str = "/static/spoiler.svg";
+/// Longest original filename shown in full above a file; longer ones are
+/// shortened, with the whole name in the tooltip.
+const SHOWN_NAME_CHARS: usize = 40;
+/// A post's files side by side, in the order they were uploaded.
+fn render_files(board: &str, post: &Post) -> String {
+    if post.files.is_empty() {
+        return String::new();
+    }
+    let boxes: Vec<String> = post.files.iter().map(|file| render_file(board, post.post_no, file)).collect();
+    format!(r#"<div class="files">{}</div>"#, boxes.concat())
+}
+/// One file box: the original name, size, dimensions and format above the
+/// file, as on classic imageboards. Images show their thumbnail; clips play
+/// in place, with their extracted frame or a placeholder icon as the picture.
+fn render_file(board: &str, post_no: i64, post_file: &PostFile) -> String {
+    let file = &post_file.filename;
+    let thumb = post_file.thumbname.as_deref().filter(|thumb| !thumb.is_empty()).map(|thumb| format!("/thumbs/{}", thumb));
+    let mut details = Vec::new();
+    if let Some(size) = post_file.size {
+        details.push(file_size(size));
+    }
+    if let (Some(width), Some(height)) = (post_file.width, post_file.height) {
+        details.push(format!("{}×{}", width, height));
+    }
+    if let Some(ms) = post_file.duration_ms {
+        details.push(clip_length(ms));
+    }
+    if let Some((_, ext)) = file.rsplit_once('.') {
+        details.push(ext.to_uppercase());
+    }
+    let name = &post_file.original_name;
+    // The name often gives away what a spoiler hides.
+    let (name, shown) = if post_file.spoiler {
+        (String::new(), "Spoiler image".to_string())
+    } else if name.chars().count() > SHOWN_NAME_CHARS {
+        (name.clone(), format!("{}(...)", name.chars().take(SHOWN_NAME_CHARS - 5).collect::<String>()))
+    } else {
+        (name.clone(), name.clone())
+    };
+    let info = format!(
+        r#"<span class="fileinfo">File: <a href="/uploads/{}" target="_blank" title="{}">{}</a> ({}) <a href="/{}/file/{}/{}" class="download">[Download]</a></span>"#,
+        file,
+        escape(&name),
+        escape(&shown),
+        details.join(", "),
+        board,
+        post_no,
+        post_file.position
+    );
+    // Clips show the spoiler as their picture; nothing is fetched before play.
+    let picture = |placeholder: &'static str| {
+        if post_file.spoiler {
+            SPOILER_IMAGE.to_string()
+        } else {
+            thumb.clone().unwrap_or_else(|| placeholder.to_string())
         }
+    };
+    let body = match post_file.kind.as_str() {
+        "video" => format!(
+            r#"<video src="/uploads/{}" poster="{}" class="post-image" controls preload="none"></video>"#,
+            file,
+            picture("/static/video.svg")
+        ),
+        "audio" => format!(
+            r#"<img src="{}" class="post-image" alt="">
+    <audio src="/uploads/{}" class="post-audio" controls preload="none"></audio>"#,
+            picture("/static/audio.svg"),
+            file
+        ),
+        _ => match thumb {
+            Some(thumb) => {
+                let link = format!(
+                    r#"<a href="/uploads/{}" target="_blank">
+        <img src="{}" class="post-image">
+    </a>"#,
+                    file, thumb
+                );
+                // Clicking the spoiler swaps in the thumbnail, no script needed.
+                if post_file.spoiler {
+                    format!(
+                        r#"<details class="spoiler"><summary><img src="{}" class="post-image" alt="Spoiler"></summary>{}</details>"#,
+                        SPOILER_IMAGE, link
+                    )
+                } else {
+                    link
+                }
+            }
+            None => String::new(),
+        },
+    };
+    format!(
+        r#"<span class="file">
+    {}
+    {}
+</span>"#,
+        info, body
+    )
+}
+
+/// "512 B", "1.1 KB" or "2.3 MB".
+fn file_size(bytes: i64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+/// "1:05" or "1:02:05".
+fn clip_length(ms: i32) -> String {
+    let seconds = ms / 1000;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
     }
+}
+
+fn render_post(html: &mut String, viewer: &Viewer, board: &str, post: &Post, is_op: bool, reply_count: Option<usize>) {
+    html.push_str(&format!("<div id=\"{}\" class=\"post {}\">", post.post_no, if is_op { "op" } else { "reply" }));
+    html.push_str("<div class=\"intro\">");
+    html.push_str(&render_files(board, post));
     if let Some(sub) = &post.subject {
         if !sub.is_empty() {
             html.push_str(&format!("<span class=\"subject\">{}</span> ", escape(sub)));
@@ -461,14 +1612,24 @@ fn render_post(html: &mut String, post: &Post, is_op: bool, reply_count: Option<
     }
     let display_name = if post.name.is_empty() { "Anonymous" } else { &post.name };
     html.push_str(&format!(
-        "<span class=\"name\">{}</span>",
-        escape(display_name)
+        "<span class=\"name\">{}</span> {}",
+        escape(display_name),
+        viewer.time(post.created_at)
+    ));
+    html.push_str(&format!(
+        " <a class=\"post_no\" href=\"/{}/thread/{}#{}\">No.{}</a>",
+        board, post.thread_id, post.post_no, post.post_no
     ));
     if is_op {
         let count_str = if let Some(c) = reply_count { format!(" [{}]", c) } else { "".to_string() };
-        html.push_str(&format!(" <a href=\"/thread/{}\">Reply{}</a>", post.thread_id, count_str));
+        html.push_str(&format!(" <a href=\"/{}/thread/{}\">Reply{}</a>", board, post.thread_id, count_str));
     }
     html.push_str("</div><div class=\"body\">");
+    if let Some(pos) = post.fen.as_deref().and_then(chess::parse_fen) {
+        html.push_str(&format!("<div class=\"diagram\">{}</div>", chess::diagram_html(&pos)));
+    } else if let Some(pos) = post.pgn.as_deref().and_then(|pgn| pgn::positions(pgn).ok()?.pop()) {
+        html.push_str(&format!("<div class=\"diagram\">{}</div>", chess::diagram_html(&pos)));
+    }
     if let Some(msg) = &post.message {
         if !msg.is_empty() {
             let quote_re = Regex::new(r"&gt;&gt;(\d+)").unwrap();
@@ -492,20 +1653,25 @@ fn render_post(html: &mut String, post: &Post, is_op: bool, reply_count: Option<
             }
         }
     }
+    if let Some(pgn) = &post.pgn {
+        html.push_str(&format!("<div class=\"pgn\">{}</div>", escape(pgn)));
+    }
     html.push_str("</div></div>");
 }
-fn render_pagination(page: u64, total_pages: u64) -> String {
+/// `base` is the link prefix up to the page parameter, e.g. `/?` or
+/// `/search?q=rook&`, so other query parameters survive paging.
+fn render_pagination(base: &str, page: u64, total_pages: u64) -> String {
     if total_pages <= 1 {
         return String::new();
     }
     let mut s = r#"<div class="pagination">"#.to_string();
     if page > 1 {
-        s.push_str(&format!(r#"<a href="/?page={}">« Prev</a>"#, page - 1));
+        s.push_str(&format!(r#"<a href="{}page={}">« Prev</a>"#, base, page - 1));
     }
     let start = if page > 3 { page - 2 } else { 1 };
     let end = if page + 2 > total_pages { total_pages } else { page + 2 };
     if start > 1 {
-        s.push_str(r#"<a href="/?page=1">1</a>"#);
+        s.push_str(&format!(r#"<a href="{}page=1">1</a>"#, base));
         if start > 2 {
             s.push_str("<span>...</span>");
         }
@@ -514,21 +1680,24 @@ fn render_pagination(page: u64, total_pages: u64) -> String {
         if p == page {
             s.push_str(&format!(r#"<span class="current">{}</span>"#, p));
         } else {
-            s.push_str(&format!(r#"<a href="/?page={}">{}</a>"#, p, p));
+            s.push_str(&format!(r#"<a href="{}page={}">{}</a>"#, base, p, p));
         }
     }
     if end < total_pages {
         if end < total_pages - 1 {
             s.push_str("<span>...</span>");
         }
-        s.push_str(&format!(r#"<a href="/?page={}">{}</a>"#, total_pages, total_pages));
+        s.push_str(&format!(r#"<a href="{}page={}">{}</a>"#, base, total_pages, total_pages));
     }
     if page < total_pages {
-        s.push_str(&format!(r#"<a href="/?page={}">Next »</a>"#, page + 1));
+        s.push_str(&format!(r#"<a href="{}page={}">Next »</a>"#, base, page + 1));
     }
     s.push_str("</div>");
     s
 }
+fn encode_query(s: &str) -> String {
+    url::form_urlencoded::byte_serialize(s.as_bytes()).collect()
+}
 fn escape(s: &str) -> String {
     s.replace('&', "&amp;")
         .replace('<', "&lt;")
@@ -0,0 +1,213 @@
+use crate::config::Config;
+use crate::storage::{Area, Storage};
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// How long ffprobe or ffmpeg may take on one upload before it is killed.
+const TOOL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `ftyp` brands of MP4 files browsers play. QuickTime and HEIF share the
+/// box layout but are not accepted.
+const MP4_BRANDS: [&[u8; 4]; 11] =
+    [b"isom", b"iso2", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"dash", b"M4V ", b"M4A "];
+
+/// Video and audio containers accepted next to images.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Container {
+    WebM,
+    Mp4,
+    Mp3,
+    Ogg,
+    Flac,
+}
+
+impl Container {
+    /// Container of an upload going by its leading bytes.
+    pub fn sniff(bytes: &[u8]) -> Option<Container> {
+        let head = &bytes[..bytes.len().min(64)];
+        if head.starts_with(b"\x1A\x45\xDF\xA3") {
+            // Matroska too starts with an EBML header; only the "webm"
+            // doctype is one browsers play.
+            return head.windows(4).any(|window| window == b"webm").then_some(Container::WebM);
+        }
+        if head.get(4..8) == Some(b"ftyp".as_slice()) {
+            let brand = head.get(8..12)?;
+            return MP4_BRANDS.iter().any(|known| known.as_slice() == brand).then_some(Container::Mp4);
+        }
+        if head.starts_with(b"OggS") {
+            return Some(Container::Ogg);
+        }
+        if head.starts_with(b"fLaC") {
+            return Some(Container::Flac);
+        }
+        // An ID3 tag, or straight away an MPEG frame header for layer III.
+        let mpeg_frame = head.len() >= 2 && head[0] == 0xFF && head[1] & 0xE6 == 0xE2;
+        if head.starts_with(b"ID3") || mpeg_frame {
+            return Some(Container::Mp3);
+        }
+        None
+    }
+
+    pub fn from_extension(ext: &str) -> Option<Container> {
+        match ext {
+            "webm" => Some(Container::WebM),
+            "mp4" => Some(Container::Mp4),
+            "mp3" => Some(Container::Mp3),
+            "ogg" => Some(Container::Ogg),
+            "flac" => Some(Container::Flac),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Container::WebM => "webm",
+            Container::Mp4 => "mp4",
+            Container::Mp3 => "mp3",
+            Container::Ogg => "ogg",
+            Container::Flac => "flac",
+        }
+    }
+
+    /// What the upload most likely holds when ffprobe can't say.
+    fn default_kind(self) -> &'static str {
+        match self {
+            Container::WebM | Container::Mp4 => "video",
+            Container::Mp3 | Container::Ogg | Container::Flac => "audio",
+        }
+    }
+}
+
+/// What is known about a stored clip, for the `files` row.
+#[derive(Debug)]
+pub struct MediaInfo {
+    /// `video` or `audio`.
+    pub kind: &'static str,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub duration_ms: Option<i32>,
+    pub thumbname: Option<String>,
+}
+
+/// Probes the clip at `path`, the upload's spool file, and extracts a
+/// thumbnail from its first video stream or its cover art. Without ffprobe
+/// and ffmpeg only the kind is known and the post shows a placeholder
+/// instead of a thumbnail.
+pub async fn inspect(config: &Config, storage: &dyn Storage, container: Container, path: &Path, uuid: &str) -> MediaInfo {
+    let Some(probe) = probe(config, path).await else {
+        return MediaInfo { kind: container.default_kind(), width: None, height: None, duration_ms: None, thumbname: None };
+    };
+    // Cover art in an audio file is a one-frame video stream; it makes a
+    // thumbnail but not a video.
+    let picture = probe.video.or(probe.cover);
+    let fits = picture.is_some_and(|(width, height)| {
+        (width as u64) * (height as u64) <= config.limits.max_image_pixels
+    });
+    let thumbname = if fits { thumbnail(config, storage, path, &format!("{}_thumb.jpg", uuid)).await } else { None };
+    let (width, height) = probe.video.map_or((None, None), |(width, height)| (Some(width as i32), Some(height as i32)));
+    MediaInfo {
+        kind: if probe.video.is_some() { "video" } else { "audio" },
+        width,
+        height,
+        duration_ms: probe.duration_ms,
+        thumbname,
+    }
+}
+
+/// Logs once at startup whether thumbnails for clips can be made.
+pub async fn check_tools(config: &Config) {
+    for tool in [&config.thumbnails.ffprobe, &config.thumbnails.ffmpeg] {
+        let found = run(Command::new(tool).arg("-version")).await.is_some();
+        if !found {
+            tracing::warn!(tool = %tool.display(), "not found; video and audio posts get placeholder thumbnails");
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Probe {
+    video: Option<(u32, u32)>,
+    cover: Option<(u32, u32)>,
+    duration_ms: Option<i32>,
+}
+
+async fn probe(config: &Config, path: &Path) -> Option<Probe> {
+    let output = run(
+        Command::new(&config.thumbnails.ffprobe)
+            .args(["-v", "error", "-show_entries"])
+            .arg("format=duration:stream=codec_type,width,height:stream_disposition=attached_pic")
+            .args(["-of", "compact=p=0"])
+            .arg(path),
+    )
+    .await?;
+    let mut probe = Probe::default();
+    // One line per stream, then one for the format:
+    // codec_type=video|width=1280|height=720|disposition:attached_pic=0
+    for line in String::from_utf8_lossy(&output).lines() {
+        let field = |name: &str| {
+            line.split('|').filter_map(|pair| pair.split_once('=')).find(|(key, _)| *key == name).map(|(_, value)| value)
+        };
+        if let Some(duration) = field("duration").and_then(|value| value.parse::<f64>().ok()) {
+            probe.duration_ms = Some((duration * 1000.0).round() as i32);
+        }
+        if field("codec_type") != Some("video") {
+            continue;
+        }
+        let size = field("width").and_then(|w| w.parse().ok()).zip(field("height").and_then(|h| h.parse().ok()));
+        if field("disposition:attached_pic") == Some("1") {
+            probe.cover = probe.cover.or(size);
+        } else {
+            probe.video = probe.video.or(size);
+        }
+    }
+    Some(probe)
+}
+
+/// Picks a representative frame from the first video stream, shrunk to
+/// fit the thumbnail box, and stores it.
+async fn thumbnail(config: &Config, storage: &dyn Storage, path: &Path, thumbname: &str) -> Option<String> {
+    let thumb = tempfile::Builder::new().suffix(".jpg").tempfile().ok()?;
+    let (width, height) = (config.thumbnails.width, config.thumbnails.height);
+    let filter = format!("thumbnail,scale='min({},iw)':'min({},ih)':force_original_aspect_ratio=decrease", width, height);
+    run(
+        Command::new(&config.thumbnails.ffmpeg)
+            .args(["-v", "error", "-nostdin", "-y", "-i"])
+            .arg(path)
+            .args(["-map", "0:v:0", "-frames:v", "1", "-vf", &filter])
+            .arg(thumb.path()),
+    )
+    .await?;
+    let saved = match tokio::fs::read(thumb.path()).await {
+        Ok(bytes) => storage.put(Area::Thumbs, thumbname, bytes).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = saved {
+        tracing::warn!(error = %e, "could not store a clip thumbnail");
+        return None;
+    }
+    Some(thumbname.to_string())
+}
+
+/// Stdout of a successful run; `None` if the tool is missing, fails or
+/// takes longer than `TOOL_TIMEOUT`.
+async fn run(command: &mut Command) -> Option<Vec<u8>> {
+    command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).kill_on_drop(true);
+    let output = match tokio::time::timeout(TOOL_TIMEOUT, command.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => {
+            tracing::debug!(error = %e, "could not start media tool");
+            return None;
+        }
+        Err(_) => {
+            tracing::warn!("media tool timed out");
+            return None;
+        }
+    };
+    if !output.status.success() {
+        tracing::warn!(stderr = %String::from_utf8_lossy(&output.stderr).trim(), "media tool failed");
+        return None;
+    }
+    Some(output.stdout)
+}
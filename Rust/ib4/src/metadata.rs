@@ -0,0 +1,134 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use std::io::Cursor;
+
+/// PNG chunks that carry text, EXIF or timestamps rather than pixels.
+const PNG_METADATA: [&[u8; 4]; 5] = [b"tEXt", b"zTXt", b"iTXt", b"eXIf", b"tIME"];
+
+/// Quality used when a photo has to be re-encoded to turn it upright.
+const JPEG_QUALITY: u8 = 90;
+
+/// Returns `bytes` without EXIF, XMP, IPTC, comments or PNG text chunks.
+/// The pixels are left untouched, except for images whose EXIF asks for a
+/// rotation: those are decoded, turned upright and re-encoded, since the
+/// orientation tag goes with the rest of the EXIF. `None` means the file is
+/// too damaged to clean.
+pub fn strip(bytes: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    let cleaned = match format {
+        ImageFormat::Jpeg => strip_jpeg(bytes),
+        ImageFormat::Png => strip_png(bytes),
+        ImageFormat::WebP => strip_webp(bytes),
+        _ => return Some(bytes.to_vec()),
+    }?;
+    let mut decoder = ImageReader::with_format(Cursor::new(bytes), format).into_decoder().ok()?;
+    match decoder.orientation().unwrap_or(Orientation::NoTransforms) {
+        Orientation::NoTransforms => Some(cleaned),
+        // Re-encoding would keep only the first frame.
+        _ if format == ImageFormat::WebP && webp_is_animated(bytes) => Some(cleaned),
+        orientation => {
+            let mut img = DynamicImage::from_decoder(decoder).ok()?;
+            img.apply_orientation(orientation);
+            encode(&img, format)
+        }
+    }
+}
+
+/// Re-encodes `img`; the encoders write no metadata of their own.
+fn encode(img: &DynamicImage, format: ImageFormat) -> Option<Vec<u8>> {
+    let mut out = Cursor::new(Vec::new());
+    if format == ImageFormat::Jpeg {
+        img.to_rgb8().write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY)).ok()?;
+    } else {
+        img.write_to(&mut out, format).ok()?;
+    }
+    Some(out.into_inner())
+}
+
+/// Copies every segment up to the image data except APP1 (EXIF, XMP),
+/// APP13 (IPTC), the other vendor APPn blocks and comments. APP0 (JFIF),
+/// APP2 (ICC colour profile) and APP14 (Adobe colour transform) are needed
+/// to show the colours right and stay.
+fn strip_jpeg(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut out = bytes.get(..2)?.to_vec();
+    let mut pos = 2;
+    loop {
+        let marker = *bytes.get(pos + 1)?;
+        if bytes[pos] != 0xFF {
+            return None;
+        }
+        // Fill bytes may precede a marker.
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        // Markers without a length field.
+        if matches!(marker, 0x01 | 0xD0..=0xD9) {
+            out.extend_from_slice(&bytes[pos..pos + 2]);
+            pos += 2;
+            continue;
+        }
+        let len = u16::from_be_bytes([*bytes.get(pos + 2)?, *bytes.get(pos + 3)?]) as usize;
+        let end = pos + 2 + len;
+        let segment = bytes.get(pos..end)?;
+        // Start of scan: entropy-coded data follows, copied as is.
+        if marker == 0xDA {
+            out.extend_from_slice(&bytes[pos..]);
+            return Some(out);
+        }
+        let drop = matches!(marker, 0xE1 | 0xE3..=0xED | 0xEF | 0xFE);
+        if !drop {
+            out.extend_from_slice(segment);
+        }
+        pos = end;
+    }
+}
+
+fn strip_png(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut out = bytes.get(..8)?.to_vec();
+    let mut pos = 8;
+    loop {
+        let len = u32::from_be_bytes(bytes.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let kind = bytes.get(pos + 4..pos + 8)?;
+        // Length, type, data and CRC.
+        let end = pos.checked_add(12 + len)?;
+        let chunk = bytes.get(pos..end)?;
+        if !PNG_METADATA.iter().any(|name| name.as_slice() == kind) {
+            out.extend_from_slice(chunk);
+        }
+        if kind == b"IEND" {
+            return Some(out);
+        }
+        pos = end;
+    }
+}
+
+fn webp_is_animated(bytes: &[u8]) -> bool {
+    bytes.get(12..16) == Some(b"VP8X".as_slice()) && bytes.get(20).is_some_and(|flags| flags & 0x02 != 0)
+}
+
+/// Drops the EXIF and XMP chunks and clears their flags in the VP8X header.
+fn strip_webp(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut out = bytes.get(..12)?.to_vec();
+    let mut pos = 12;
+    while pos < bytes.len() {
+        let kind = bytes.get(pos..pos + 4)?;
+        let len = u32::from_le_bytes(bytes.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        // Chunks are padded to an even length.
+        let end = pos.checked_add(8 + len + len % 2)?;
+        let chunk = bytes.get(pos..end)?;
+        match kind {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let flags_at = out.len() + 8;
+                out.extend_from_slice(chunk);
+                *out.get_mut(flags_at)? &= !0x0C;
+            }
+            _ => out.extend_from_slice(chunk),
+        }
+        pos = end;
+    }
+    let riff_size = u32::try_from(out.len() - 8).ok()?;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Some(out)
+}
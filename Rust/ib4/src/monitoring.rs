@@ -0,0 +1,101 @@
+use crate::error::{AppError, AppResult};
+use crate::SharedState;
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram, Unit};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use std::time::{Duration, Instant};
+
+/// Bucket bounds in seconds for every histogram; page renders and thumbnail
+/// runs both land well inside this range.
+const BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Where `main` nests the static, upload and thumbnail directories.
+const FILE_MOUNTS: &[&str] = &["/static", "/uploads", "/thumbs"];
+
+/// Installs the global recorder. Until this runs the `metrics` macros are
+/// no-ops, so a disabled endpoint costs nothing.
+pub fn install() -> PrometheusHandle {
+    let handle = PrometheusBuilder::new()
+        .set_buckets(BUCKETS)
+        .expect("bucket list is not empty")
+        .install_recorder()
+        .expect("metrics recorder installed twice");
+    describe_counter!("chessboard_http_requests_total", "Requests handled, by route and status");
+    describe_histogram!("chessboard_http_request_duration_seconds", Unit::Seconds, "Time to produce a response, by route");
+    describe_counter!("chessboard_posts_created_total", "Posts stored, by board");
+    describe_counter!("chessboard_threads_created_total", "Threads started, by board");
+    describe_counter!("chessboard_posts_rejected_total", "Posts turned away, by board and reason");
+    describe_counter!("chessboard_upload_bytes_total", Unit::Bytes, "Bytes of uploaded files written, by board");
+    describe_counter!("chessboard_upload_duplicates_total", "Uploads matching a stored file, so nothing was written, by board");
+    describe_histogram!("chessboard_thumbnail_duration_seconds", Unit::Seconds, "Time to decode, resize and save a thumbnail");
+    describe_counter!("chessboard_thumbnail_failures_total", "Uploads whose thumbnail could not be made");
+    describe_gauge!("chessboard_db_pool_connections", "Open database connections");
+    describe_gauge!("chessboard_db_pool_idle_connections", "Open database connections not in use");
+    describe_gauge!("chessboard_db_pool_max_connections", "Configured database pool size");
+    handle
+}
+
+/// Counts and times every routed request. Labels use the route pattern, not
+/// the URI, so thread numbers don't turn into new series.
+pub async fn track(request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let method = request.method().to_string();
+    // File services are nested rather than routed, so they carry no
+    // `MatchedPath`; label them by mount point instead.
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        None => FILE_MOUNTS
+            .iter()
+            .find(|mount| request.uri().path().starts_with(&format!("{}/", mount)))
+            .map_or("unmatched".to_string(), |mount| format!("{}/*", mount)),
+    };
+    let response = next.run(request).await;
+    let status = response.status().as_u16().to_string();
+    let labels = [("method", method), ("route", route), ("status", status)];
+    histogram!("chessboard_http_request_duration_seconds", &labels[..2]).record(started.elapsed());
+    counter!("chessboard_http_requests_total", &labels).increment(1);
+    response
+}
+
+/// `/metrics` in the Prometheus text format. Pool gauges are read at scrape
+/// time since the pool has no hooks to report changes.
+pub async fn render(State(state): State<SharedState>) -> AppResult<String> {
+    let Some(handle) = &state.metrics else {
+        return Err(AppError::NotFound("Metrics are disabled".to_string()));
+    };
+    gauge!("chessboard_db_pool_connections").set(state.db.size() as f64);
+    gauge!("chessboard_db_pool_idle_connections").set(state.db.num_idle() as f64);
+    gauge!("chessboard_db_pool_max_connections").set(state.db.options().get_max_connections() as f64);
+    Ok(handle.render())
+}
+
+pub fn post_created(board: &str, new_thread: bool) {
+    counter!("chessboard_posts_created_total", "board" => board.to_string()).increment(1);
+    if new_thread {
+        counter!("chessboard_threads_created_total", "board" => board.to_string()).increment(1);
+    }
+}
+
+pub fn post_rejected(board: &str, reason: &'static str) {
+    counter!("chessboard_posts_rejected_total", "board" => board.to_string(), "reason" => reason).increment(1);
+}
+
+pub fn upload_stored(board: &str, bytes: usize) {
+    counter!("chessboard_upload_bytes_total", "board" => board.to_string()).increment(bytes as u64);
+}
+
+pub fn upload_deduplicated(board: &str) {
+    counter!("chessboard_upload_duplicates_total", "board" => board.to_string()).increment(1);
+}
+
+pub fn thumbnail_made(elapsed: Duration) {
+    histogram!("chessboard_thumbnail_duration_seconds").record(elapsed);
+}
+
+pub fn thumbnail_failed() {
+    counter!("chessboard_thumbnail_failures_total").increment(1);
+}
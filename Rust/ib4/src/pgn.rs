@@ -0,0 +1,151 @@
+use shakmaty::{fen::Fen, san::SanPlus, CastlingMode, Chess, Position};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum PgnError {
+    InvalidFenTag(String),
+    InvalidSan { ply: usize, token: String },
+    IllegalMove { ply: usize, token: String },
+    NoMoves,
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgnError::InvalidFenTag(fen) => write!(f, "invalid FEN tag \"{}\"", fen),
+            PgnError::InvalidSan { ply, token } => write!(f, "unreadable move \"{}\" at ply {}", token, ply),
+            PgnError::IllegalMove { ply, token } => write!(f, "illegal move \"{}\" at ply {}", token, ply),
+            PgnError::NoMoves => write!(f, "no moves found"),
+        }
+    }
+}
+
+/// Replays the main line of a single PGN game and returns every position
+/// reached, starting with the initial one (the `[FEN]` tag if present).
+/// Comments, variations, NAGs, move numbers and the result are skipped.
+pub fn positions(pgn: &str) -> Result<Vec<Chess>, PgnError> {
+    let mut pos = Chess::default();
+    let mut movetext = String::new();
+    for line in pgn.lines() {
+        let line = line.trim();
+        if let Some(tag) = line.strip_prefix('[') {
+            if let Some(value) = tag_value(tag, "FEN") {
+                pos = Fen::from_ascii(value.as_bytes())
+                    .ok()
+                    .and_then(|fen| fen.into_position(CastlingMode::Standard).ok())
+                    .ok_or_else(|| PgnError::InvalidFenTag(value.to_string()))?;
+            }
+        } else if !line.starts_with('%') {
+            movetext.push_str(line);
+            movetext.push('\n');
+        }
+    }
+
+    let mut positions = vec![pos.clone()];
+    for token in tokens(&movetext) {
+        let ply = positions.len();
+        let san = SanPlus::from_ascii(token.as_bytes())
+            .map_err(|_| PgnError::InvalidSan { ply, token: token.clone() })?;
+        let m = san
+            .san
+            .to_move(&pos)
+            .map_err(|_| PgnError::IllegalMove { ply, token: token.clone() })?;
+        pos.play_unchecked(m);
+        positions.push(pos.clone());
+    }
+    if positions.len() == 1 {
+        return Err(PgnError::NoMoves);
+    }
+    Ok(positions)
+}
+
+fn tag_value<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let rest = tag.strip_prefix(name)?.trim_start();
+    let rest = rest.strip_prefix('"')?;
+    rest.find('"').map(|end| &rest[..end])
+}
+
+/// Splits movetext into SAN tokens, dropping everything that is not a move.
+fn tokens(movetext: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0usize;
+    let mut chars = movetext.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                // Brace comments do not nest.
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                }
+            }
+            ';' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            c if c.is_whitespace() => push_token(&mut out, &mut current),
+            c if depth == 0 => current.push(c),
+            _ => {}
+        }
+        if matches!(c, '{' | ';' | '(' | ')') {
+            push_token(&mut out, &mut current);
+        }
+    }
+    push_token(&mut out, &mut current);
+    out
+}
+
+fn push_token(out: &mut Vec<String>, current: &mut String) {
+    let token = std::mem::take(current);
+    if matches!(token.as_str(), "*" | "1-0" | "0-1" | "1/2-1/2") {
+        return;
+    }
+    // Strip a leading move number such as "12." or "12..." glued to the move,
+    // and trailing annotation glyphs such as "!", "?!" or "??".
+    let token = token
+        .trim_start_matches(|c: char| c.is_ascii_digit() || c == '.')
+        .trim_end_matches(['!', '?']);
+    if token.is_empty() || token.starts_with('$') {
+        return;
+    }
+    out.push(token.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_game_replays() {
+        let reached = positions("1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 1-0").unwrap();
+        assert_eq!(reached.len(), 7);
+    }
+
+    #[test]
+    fn annotation_glyphs_are_ignored() {
+        assert_eq!(tokens("1. e4! e5?! 2. Qh5+? Nc6!! 3. Bc4 !? g6??"), ["e4", "e5", "Qh5+", "Nc6", "Bc4", "g6"]);
+        let annotated = positions("1. e4! e5?! 2. Qh5+? Nc6!! 3. Bc4 !? Nf6?? 4. Qxf7# 1-0").unwrap();
+        let plain = positions("1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0").unwrap();
+        assert_eq!(annotated.last().unwrap().board(), plain.last().unwrap().board());
+    }
+
+    #[test]
+    fn comments_variations_and_nags_are_skipped() {
+        let reached = positions("1. e4 {best by test} (1. d4 d5) e5 $1 ; rest of line\n2. Nf3 *").unwrap();
+        assert_eq!(reached.len(), 4);
+    }
+
+    #[test]
+    fn illegal_moves_are_reported() {
+        assert!(matches!(positions("1. e4 e5 2. Ke3"), Err(PgnError::IllegalMove { ply: 3, .. })));
+        assert!(matches!(positions("1. e4 e5 2. Zz9"), Err(PgnError::InvalidSan { ply: 3, .. })));
+        assert!(matches!(positions("1-0"), Err(PgnError::NoMoves)));
+    }
+}
@@ -0,0 +1,52 @@
+use crate::error::AppResult;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use sqlx::PgPool;
+
+/// Perceptual hash of an image, or `None` if it does not decode. Animated
+/// images are hashed by their first frame.
+pub fn of_image(bytes: &[u8], format: ImageFormat) -> Option<i64> {
+    image::load_from_memory_with_format(bytes, format).ok().map(|img| dhash(&img))
+}
+
+/// 64-bit difference hash: the image shrunk to 9×8 grey pixels, one bit per
+/// pair of horizontal neighbours, set where the left one is brighter.
+/// Resizing, recompressing and small edits flip few bits, so near copies
+/// are a small Hamming distance apart. Stored as `BIGINT`, hence signed.
+pub fn dhash(img: &DynamicImage) -> i64 {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash = hash << 1 | u64::from(small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0]);
+        }
+    }
+    hash as i64
+}
+
+/// Number of bits in which two hashes differ.
+pub fn distance(a: i64, b: i64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// How hashes are written for moderators: 16 hex digits.
+pub fn to_hex(hash: i64) -> String {
+    format!("{:016x}", hash as u64)
+}
+
+pub fn from_hex(text: &str) -> Option<i64> {
+    let text = text.trim();
+    (text.len() == 16).then(|| u64::from_str_radix(text, 16).ok()).flatten().map(|hash| hash as i64)
+}
+
+/// The banned hash nearest to `hash`, if it is at most `max_distance` bits
+/// away. The list is short enough to compare in full.
+pub async fn find_ban(pool: &PgPool, hash: i64, max_distance: u32) -> AppResult<Option<i64>> {
+    let banned = sqlx::query_scalar!("SELECT phash FROM banned_images").fetch_all(pool).await?;
+    Ok(banned
+        .into_iter()
+        .map(|banned| (distance(hash, banned), banned))
+        .filter(|(bits, _)| *bits <= max_distance)
+        .min()
+        .map(|(_, banned)| banned))
+}
@@ -0,0 +1,299 @@
+use crate::config::{Config, PathsConfig, S3Config, StorageBackend};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use object_store::aws::{AmazonS3, AmazonS3Builder};
+use object_store::ObjectStore;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+use uuid::Uuid;
+
+/// The kinds of stored file, each kept apart from the others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Area {
+    Uploads,
+    Thumbs,
+    /// Uploads as received; never served.
+    Originals,
+}
+
+impl Area {
+    fn as_str(self) -> &'static str {
+        match self {
+            Area::Uploads => "uploads",
+            Area::Thumbs => "thumbs",
+            Area::Originals => "originals",
+        }
+    }
+}
+
+/// Where uploads, thumbnails and originals live. Names are the flat ones
+/// kept in the database; how they are laid out is up to the backend.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Stores `bytes` under `name`, replacing any earlier file. Readers see
+    /// either the old file or the whole new one.
+    async fn put(&self, area: Area, name: &str, bytes: Vec<u8>) -> io::Result<()>;
+
+    /// Stores the file at `path` under `name`, like `put`.
+    async fn put_file(&self, area: Area, name: &str, path: &Path) -> io::Result<()> {
+        self.put(area, name, tokio::fs::read(path).await?).await
+    }
+
+    /// Fails with `NotFound` if there is no such file.
+    async fn get(&self, area: Area, name: &str) -> io::Result<Vec<u8>>;
+
+    /// Removing a file that is already gone is not an error.
+    async fn delete(&self, area: Area, name: &str) -> io::Result<()>;
+
+    /// Answers a GET or HEAD for the file, with the content type its
+    /// extension implies.
+    async fn serve(&self, area: Area, name: &str, _request: Request) -> io::Result<Response> {
+        let bytes = self.get(area, name).await?;
+        Ok(([(header::CONTENT_TYPE, HeaderValue::from_static(content_type(name)))], bytes).into_response())
+    }
+}
+
+/// The backend `storage.backend` selects. The local one first moves files
+/// from before sharding into their shard directories.
+pub fn open(config: &Config) -> io::Result<Arc<dyn Storage>> {
+    Ok(match config.storage.backend {
+        StorageBackend::Local => Arc::new(LocalStorage::open(&config.paths)?),
+        StorageBackend::S3 => Arc::new(S3Storage::open(&config.storage.s3)?),
+    })
+}
+
+/// Whether `name` can be a stored file's name: letters, digits, dots,
+/// dashes and underscores, not starting with a dot. Anything else in a URL
+/// is turned away before it reaches a backend.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 255
+        && !name.starts_with('.')
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"._-".contains(&b))
+}
+
+fn content_type(name: &str) -> &'static str {
+    let ext = name.rsplit_once('.').map_or("", |(_, ext)| ext);
+    match ext.to_ascii_lowercase().as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "webm" => "video/webm",
+        "mp4" => "video/mp4",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "flac" => "audio/flac",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Files under the `paths` directories, sharded two levels deep by the
+/// first four characters of the name (`ab/cd/abcd….png`) so no directory
+/// grows past a few thousand entries.
+pub struct LocalStorage {
+    uploads: PathBuf,
+    thumbs: PathBuf,
+    originals: Option<PathBuf>,
+}
+
+impl LocalStorage {
+    fn open(paths: &PathsConfig) -> io::Result<LocalStorage> {
+        let storage = LocalStorage {
+            uploads: paths.uploads.clone(),
+            thumbs: paths.thumbs.clone(),
+            originals: paths.originals.clone(),
+        };
+        for area in [Area::Uploads, Area::Thumbs, Area::Originals] {
+            if let Ok(dir) = storage.dir(area) {
+                storage.shard_existing(area, dir)?;
+            }
+        }
+        Ok(storage)
+    }
+
+    fn dir(&self, area: Area) -> io::Result<&Path> {
+        match area {
+            Area::Uploads => Ok(&self.uploads),
+            Area::Thumbs => Ok(&self.thumbs),
+            Area::Originals => self
+                .originals
+                .as_deref()
+                .ok_or_else(|| io::Error::other("paths.originals is not set")),
+        }
+    }
+
+    fn path(&self, area: Area, name: &str) -> io::Result<PathBuf> {
+        let dir = self.dir(area)?;
+        Ok(match shard(name) {
+            Some((first, second)) => dir.join(first).join(second).join(name),
+            None => dir.join(name),
+        })
+    }
+
+    /// Where `name` goes and a temporary file next to it to write first.
+    async fn prepare(&self, area: Area, name: &str) -> io::Result<(PathBuf, PathBuf)> {
+        let path = self.path(area, name)?;
+        let dir = path.parent().expect("joined onto a directory");
+        tokio::fs::create_dir_all(dir).await?;
+        let temp = dir.join(format!(".{}.{}.tmp", name, Uuid::new_v4()));
+        Ok((path, temp))
+    }
+
+    /// Moves files left directly in `dir` by earlier versions into their
+    /// shard directories.
+    fn shard_existing(&self, area: Area, dir: &Path) -> io::Result<()> {
+        let mut moved = 0;
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            if !entry.file_type()?.is_file() || shard(name).is_none() {
+                continue;
+            }
+            let path = self.path(area, name)?;
+            std::fs::create_dir_all(path.parent().expect("joined onto a directory"))?;
+            std::fs::rename(entry.path(), &path)?;
+            moved += 1;
+        }
+        if moved > 0 {
+            tracing::info!(area = area.as_str(), files = moved, "moved files into shard directories");
+        }
+        Ok(())
+    }
+}
+
+/// Shard directories for `name`, or `None` for names too short or odd to
+/// shard, such as probe files, which then sit in the top directory.
+fn shard(name: &str) -> Option<(&str, &str)> {
+    let prefix = name.get(..4)?;
+    let usable = name.len() > 4 && prefix.bytes().all(|b| b.is_ascii_alphanumeric());
+    usable.then(|| prefix.split_at(2))
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    /// Writes a temporary file next to the target, syncs it and renames it
+    /// into place.
+    async fn put(&self, area: Area, name: &str, bytes: Vec<u8>) -> io::Result<()> {
+        let (path, temp) = self.prepare(area, name).await?;
+        let written = async {
+            let mut file = tokio::fs::File::create(&temp).await?;
+            file.write_all(&bytes).await?;
+            file.sync_all().await?;
+            tokio::fs::rename(&temp, &path).await
+        }
+        .await;
+        if written.is_err() {
+            let _ = tokio::fs::remove_file(&temp).await;
+        }
+        written
+    }
+
+    /// Copies rather than renames: `source` is usually in the system's
+    /// temporary directory, which may be another file system.
+    async fn put_file(&self, area: Area, name: &str, source: &Path) -> io::Result<()> {
+        let (path, temp) = self.prepare(area, name).await?;
+        let written = async {
+            tokio::fs::copy(source, &temp).await?;
+            tokio::fs::OpenOptions::new().write(true).open(&temp).await?.sync_all().await?;
+            tokio::fs::rename(&temp, &path).await
+        }
+        .await;
+        if written.is_err() {
+            let _ = tokio::fs::remove_file(&temp).await;
+        }
+        written
+    }
+
+    async fn get(&self, area: Area, name: &str) -> io::Result<Vec<u8>> {
+        tokio::fs::read(self.path(area, name)?).await
+    }
+
+    async fn delete(&self, area: Area, name: &str) -> io::Result<()> {
+        match tokio::fs::remove_file(self.path(area, name)?).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Streams the file from disk, with range requests so clips can seek.
+    /// The type comes from the list of upload formats, not the file
+    /// server's guess, so a stray file under another extension, such as
+    /// `.html`, is only ever a download.
+    async fn serve(&self, area: Area, name: &str, request: Request) -> io::Result<Response> {
+        let path = self.path(area, name)?;
+        if !tokio::fs::metadata(&path).await?.is_file() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        let mut response = ServeFile::new(path).oneshot(request).await.unwrap_or_else(|never| match never {});
+        response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type(name)));
+        Ok(response.map(Body::new))
+    }
+}
+
+/// Objects in one bucket of S3 or an S3-compatible server, keyed
+/// `<area>/<name>`. Files are sent in one request, so are read into memory
+/// first; uploads are small enough for that. Served through the
+/// application, whole: range requests are not supported.
+pub struct S3Storage {
+    bucket: AmazonS3,
+}
+
+impl S3Storage {
+    fn open(config: &S3Config) -> io::Result<S3Storage> {
+        let mut builder = AmazonS3Builder::from_env().with_bucket_name(&config.bucket).with_region(&config.region);
+        if let Some(endpoint) = &config.endpoint {
+            builder = builder.with_endpoint(endpoint).with_allow_http(endpoint.starts_with("http://"));
+        }
+        if let Some(key) = &config.access_key_id {
+            builder = builder.with_access_key_id(key);
+        }
+        if let Some(secret) = &config.secret_access_key {
+            builder = builder.with_secret_access_key(secret);
+        }
+        Ok(S3Storage { bucket: builder.build().map_err(io_error)? })
+    }
+
+    fn key(area: Area, name: &str) -> object_store::path::Path {
+        object_store::path::Path::from(format!("{}/{}", area.as_str(), name))
+    }
+}
+
+fn io_error(e: object_store::Error) -> io::Error {
+    match e {
+        object_store::Error::NotFound { .. } => io::Error::new(io::ErrorKind::NotFound, e),
+        e => io::Error::other(e),
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, area: Area, name: &str, bytes: Vec<u8>) -> io::Result<()> {
+        self.bucket.put(&Self::key(area, name), bytes.into()).await.map_err(io_error)?;
+        Ok(())
+    }
+
+    async fn get(&self, area: Area, name: &str) -> io::Result<Vec<u8>> {
+        let object = self.bucket.get(&Self::key(area, name)).await.map_err(io_error)?;
+        Ok(object.bytes().await.map_err(io_error)?.to_vec())
+    }
+
+    async fn delete(&self, area: Area, name: &str) -> io::Result<()> {
+        match self.bucket.delete(&Self::key(area, name)).await.map_err(io_error) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
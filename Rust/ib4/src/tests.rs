@@ -0,0 +1,195 @@
+use super::*;
+use axum::body::Body;
+use axum::extract::connect_info::MockConnectInfo;
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+/// A `width`×`height` PNG with some detail, so its pixel data is more than
+/// a few bytes.
+pub fn png(width: u32, height: u32) -> Vec<u8> {
+    let img = image::RgbImage::from_fn(width, height, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, (x ^ y) as u8]));
+    let mut bytes = Vec::new();
+    img.write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageFormat::Png).unwrap();
+    bytes
+}
+
+const BOUNDARY: &str = "chessboard-test-boundary";
+
+/// The app over a fresh test database, with uploads in a temporary
+/// directory.
+struct TestBoard {
+    app: Router,
+    db: PgPool,
+    static_dir: PathBuf,
+    /// Removed on drop.
+    _dir: TempDir,
+}
+
+fn board(db: PgPool) -> TestBoard {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::default();
+    config.paths.static_dir = dir.path().join("static");
+    config.paths.uploads = dir.path().join("static/uploads");
+    config.paths.thumbs = dir.path().join("static/thumbs");
+    std::fs::create_dir_all(&config.paths.uploads).unwrap();
+    std::fs::create_dir_all(&config.paths.thumbs).unwrap();
+    let static_dir = config.paths.static_dir.clone();
+    let storage = storage::open(&config).unwrap();
+    let filters = Filters::compile(&config.filters).unwrap();
+    let state = Arc::new(AppState { db: db.clone(), config, metrics: None, storage, filters });
+    let app = app(state, 16 * 1024 * 1024).layer(MockConnectInfo(SocketAddr::from(([192, 0, 2, 1], 40000))));
+    TestBoard { app, db, static_dir, _dir: dir }
+}
+
+/// A POST to `uri` with `fields` and, if given, one file.
+fn post_form(uri: &str, fields: &[(&str, &str)], file: Option<(&str, &[u8])>) -> Request {
+    let mut body = Vec::new();
+    for (name, value) in fields {
+        body.extend_from_slice(
+            format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", BOUNDARY, name, value).as_bytes(),
+        );
+    }
+    if let Some((filename, bytes)) = file {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+                BOUNDARY, filename
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(bytes);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+    Request::post(uri)
+        .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY))
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// Where a post redirected to; a rejected post carries `?error=`.
+/// Puts `bytes` where local storage keeps `name` in `area`, a directory of
+/// `static`.
+fn store(board: &TestBoard, area: &str, name: &str, bytes: &[u8]) {
+    let dir = board.static_dir.join(area).join(&name[..2]).join(&name[2..4]);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(name), bytes).unwrap();
+}
+
+async fn get(board: &TestBoard, uri: &str) -> Response {
+    board.app.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap()
+}
+
+fn location(response: &Response) -> &str {
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    response.headers()[header::LOCATION].to_str().unwrap()
+}
+
+const ANNOTATED_PGN: &str = "[Event \"Casual\"]\n\n1. e4! c5?! 2. Nf3!? {main line} d6?? 3. d4 $1 *";
+
+#[sqlx::test]
+async fn annotated_pgn_is_posted_and_indexed(db: PgPool) {
+    let board = board(db);
+    let form = post_form("/chess/", &[("subject", "Game"), ("message", "Annotated"), ("pgn", ANNOTATED_PGN)], None);
+    let response = board.app.clone().oneshot(form).await.unwrap();
+    assert_eq!(location(&response), "/chess/");
+    let (post_id, thread_id) = sqlx::query_as::<_, (i32, i64)>("SELECT id, thread_id FROM posts WHERE pgn = $1")
+        .bind(ANNOTATED_PGN)
+        .fetch_one(&board.db)
+        .await
+        .unwrap();
+    let indexed: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM post_positions WHERE post_id = $1")
+        .bind(post_id)
+        .fetch_one(&board.db)
+        .await
+        .unwrap();
+    assert_eq!(indexed, 6);
+
+    let reply = post_form(&format!("/chess/thread/{}", thread_id), &[("message", "Reply"), ("pgn", ANNOTATED_PGN)], None);
+    let response = board.app.oneshot(reply).await.unwrap();
+    assert_eq!(location(&response), format!("/chess/thread/{}", thread_id));
+}
+
+#[sqlx::test]
+async fn unreadable_pgn_is_posted_unindexed(db: PgPool) {
+    let board = board(db);
+    let form = post_form("/chess/", &[("subject", "Game"), ("message", "Odd"), ("pgn", "1. e4 e5 2. Ke3")], None);
+    let response = board.app.oneshot(form).await.unwrap();
+    assert_eq!(location(&response), "/chess/");
+    let post_id: i32 = sqlx::query_scalar("SELECT id FROM posts WHERE message = 'Odd'").fetch_one(&board.db).await.unwrap();
+    let indexed: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM post_positions WHERE post_id = $1")
+        .bind(post_id)
+        .fetch_one(&board.db)
+        .await
+        .unwrap();
+    assert_eq!(indexed, 0);
+}
+
+#[sqlx::test]
+async fn thread_filter_needs_a_board(db: PgPool) {
+    let board = board(db);
+    sqlx::query("INSERT INTO boards (slug, title, subtitle) VALUES ('openings', 'Openings', '')")
+        .execute(&board.db)
+        .await
+        .unwrap();
+    for slug in ["chess", "openings"] {
+        let form = post_form(&format!("/{}/", slug), &[("subject", "Rook endings"), ("message", "Lucena")], None);
+        location(&board.app.clone().oneshot(form).await.unwrap());
+    }
+    // Numbered per board, so both threads have the same number.
+    let threads: Vec<i64> = sqlx::query_scalar("SELECT thread_id FROM posts WHERE message = 'Lucena'")
+        .fetch_all(&board.db)
+        .await
+        .unwrap();
+    assert_eq!(threads[0], threads[1]);
+    let thread = threads[0];
+    for (uri, hits) in [
+        (format!("/search?q=lucena&thread={}", thread), 2),
+        (format!("/search?q=lucena&board=openings&thread={}", thread), 1),
+    ] {
+        let response = board.app.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(&format!("{} result(s)", hits)), "{}", body);
+    }
+}
+
+#[sqlx::test]
+async fn truncated_png_is_turned_away(db: PgPool) {
+    let board = board(db);
+    let whole = png(64, 64);
+    let form = post_form("/chess/", &[("subject", "Diagram"), ("message", "Cut off")], Some(("cut.png", &whole[..whole.len() / 2])));
+    let response = board.app.oneshot(form).await.unwrap();
+    assert!(location(&response).starts_with("/chess/?error="));
+    let posts: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM posts").fetch_one(&board.db).await.unwrap();
+    assert_eq!(posts, 0);
+}
+
+#[sqlx::test]
+async fn stored_files_are_served_by_name_with_nosniff(db: PgPool) {
+    let board = board(db);
+    store(&board, "uploads", "abcdef.png", b"\x89PNG");
+    store(&board, "thumbs", "abcdef.png", b"\x89PNG");
+    for uri in ["/uploads/abcdef.png", "/thumbs/abcdef.png"] {
+        let response = get(&board, uri).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        assert_eq!(response.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+    }
+    for uri in ["/static/uploads/ab/cd/abcdef.png", "/static/thumbs/ab/cd/abcdef.png"] {
+        assert_eq!(get(&board, uri).await.status(), StatusCode::NOT_FOUND, "{}", uri);
+    }
+    let response = get(&board, "/static/uploads/abcdef.png").await;
+    assert_eq!(response.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+}
+
+#[sqlx::test]
+async fn stray_html_is_not_served_as_html(db: PgPool) {
+    let board = board(db);
+    store(&board, "uploads", "abcdxss.html", b"<script>alert(1)</script>");
+    let response = get(&board, "/uploads/abcdxss.html").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/octet-stream");
+    assert_eq!(response.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+    assert_eq!(get(&board, "/static/uploads/ab/cd/abcdxss.html").await.status(), StatusCode::NOT_FOUND);
+}